@@ -0,0 +1,85 @@
+//! Headless cave generator. Runs the cave pipeline without a window, audio or physics and writes
+//! each cave's tile mask to `<out>/cave_<seed>.png`, the right way up to import as a mask, and its
+//! graph to `<out>/cave_<seed>.json`, and optionally `.dot` and `.graphml`.
+use std::{error::Error, fs, path::PathBuf};
+
+use bevy::math::Vec2;
+use clap::Parser;
+use procy::plugins::{
+    caves::{export::GraphFormat, generate, masks, Config},
+    terrain::MapConfig,
+};
+use rand::{thread_rng, Rng};
+
+#[derive(Parser)]
+#[command(about = "Batch-generate caves without starting the game")]
+struct Args {
+    /// Directory to write the generated files to.
+    #[arg(short, long, default_value = "caves")]
+    out: PathBuf,
+    /// Number of caves to generate.
+    #[arg(short = 'n', long, default_value_t = 1)]
+    count: u64,
+    /// Seed of the first cave, later caves use consecutive seeds. Random if omitted.
+    #[arg(short, long)]
+    seed: Option<u64>,
+    /// Map width in tiles, defaults to the game's map size.
+    #[arg(long)]
+    width: Option<u32>,
+    /// Map height in tiles, defaults to the game's map size.
+    #[arg(long)]
+    height: Option<u32>,
+    /// PNG in `assets/cave_masks` to only place rooms on the light parts of.
+    #[arg(long)]
+    seed_mask: Option<String>,
+    /// Also export each graph to GraphViz DOT.
+    #[arg(long)]
+    dot: bool,
+    /// Also export each graph to GraphML.
+    #[arg(long)]
+    graphml: bool,
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+    fs::create_dir_all(&args.out)?;
+
+    let map = MapConfig::default();
+    let size = Vec2::new(
+        args.width.unwrap_or(map.size.x) as f32,
+        args.height.unwrap_or(map.size.y) as f32,
+    );
+    let first = args.seed.unwrap_or_else(|| thread_rng().gen());
+    for seed in (0..args.count).map(|i| first.wrapping_add(i)) {
+        let config = Config {
+            seed,
+            seed_mask: args.seed_mask.clone(),
+            ..Default::default()
+        };
+        let cave = generate(size, &config);
+
+        let path = args.out.join(format!("cave_{seed}"));
+        masks::mask_to_image(&cave.mask).save(path.with_extension("png"))?;
+        fs::write(
+            path.with_extension("json"),
+            serde_json::to_string_pretty(&cave.graph)?,
+        )?;
+        for (wanted, format) in [
+            (args.dot, GraphFormat::Dot),
+            (args.graphml, GraphFormat::GraphMl),
+        ] {
+            if wanted {
+                format.write(&cave.graph, &path.with_extension(format.extension()))?;
+            }
+        }
+        println!(
+            "{seed}: {} nodes, {} edges, {} pockets, {} exits, {} spawn points",
+            cave.graph.node_count(),
+            cave.graph.edge_count(),
+            cave.stats.pockets,
+            cave.landmarks.exits.len(),
+            cave.landmarks.spawn_points.len()
+        );
+    }
+    Ok(())
+}
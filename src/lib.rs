@@ -0,0 +1,4 @@
+pub mod camera;
+pub mod math;
+pub mod plugins;
+pub mod prelude;
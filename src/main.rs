@@ -1,19 +1,18 @@
-mod camera;
-mod math;
-mod plugins;
-mod prelude;
 use bevy::log::{Level, LogPlugin};
 use iyes_perf_ui::prelude::PerfUiAllEntries;
-use plugins::{
-    caves::{caves_plugin, Caves},
-    creature::creature_plugin,
-    pathfinding::pathfinding_plugin,
-    physics::physics_plugin,
-    sound::sound_plugin,
-    spawn_tool::spawn_tool_plugin,
-    terrain::terrain_plugin,
+use procy::{
+    camera,
+    plugins::{
+        caves::{caves_plugin, Caves},
+        creature::creature_plugin,
+        pathfinding::pathfinding_plugin,
+        physics::physics_plugin,
+        sound::sound_plugin,
+        spawn_tool::spawn_tool_plugin,
+        terrain::{terrain_plugin, MapConfig},
+    },
+    prelude::*,
 };
-use prelude::*;
 
 fn main() {
     App::new()
@@ -47,11 +46,8 @@ fn main() {
         .run();
 }
 
-fn setup(mut cmd: Commands) {
+fn setup(mut cmd: Commands, map: Res<MapConfig>) {
     cmd.spawn((Camera2d, Msaa::Off));
-    cmd.spawn(Caves {
-        size: Vec2::new(256.0, 256.0),
-        ..default()
-    });
+    cmd.spawn(Caves::new(&map));
     cmd.spawn(PerfUiAllEntries::default());
 }
@@ -0,0 +1,176 @@
+use std::collections::VecDeque;
+
+use petgraph::prelude::*;
+
+use crate::prelude::*;
+
+use super::{CaveEdge, CaveNode};
+
+/// What a chamber is to the rest of the cave, by how many tunnels leave it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DegreeClass {
+    /// One tunnel, or none in a cave of a single chamber.
+    DeadEnd,
+    /// Two tunnels, one in and one out.
+    Corridor,
+    /// Three or more tunnels.
+    Hub,
+}
+
+impl DegreeClass {
+    fn of(degree: usize) -> Self {
+        match degree {
+            0 | 1 => DegreeClass::DeadEnd,
+            2 => DegreeClass::Corridor,
+            _ => DegreeClass::Hub,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct NodeAnalysis {
+    pub degree: DegreeClass,
+    /// Tunnels between the entrance and this chamber, `None` if it can't be reached.
+    pub depth: Option<usize>,
+    /// Whether there are two separate ways into this chamber.
+    pub on_cycle: bool,
+}
+
+/// Per-chamber annotations of the current cave's graph, updated whenever a new cave is swapped in.
+#[derive(Resource, Clone, Debug, Default)]
+pub struct CaveAnalysis {
+    pub entrance: Option<NodeIndex>,
+    /// Indexed by the chambers' [`NodeIndex`].
+    pub nodes: Vec<NodeAnalysis>,
+}
+
+impl CaveAnalysis {
+    /// Analyses `graph` with depths measured from `entrance`.
+    pub fn new(graph: &UnGraph<CaveNode, CaveEdge>, entrance: Option<NodeIndex>) -> Self {
+        let depths = entrance.map_or_else(
+            || vec![None; graph.node_count()],
+            |entrance| depths(graph, entrance),
+        );
+        let on_cycle = on_cycle(graph);
+        let nodes = graph
+            .node_indices()
+            .map(|node| NodeAnalysis {
+                degree: DegreeClass::of(graph.edges(node).count()),
+                depth: depths[node.index()],
+                on_cycle: on_cycle[node.index()],
+            })
+            .collect();
+        Self { entrance, nodes }
+    }
+
+    /// The chamber nearest the top of the map, where the cave is entered from the surface.
+    pub fn topmost(graph: &UnGraph<CaveNode, CaveEdge>) -> Option<NodeIndex> {
+        graph
+            .node_indices()
+            .max_by(|a, b| graph[*a].position.y.total_cmp(&graph[*b].position.y))
+    }
+
+    pub fn get(&self, node: NodeIndex) -> Option<&NodeAnalysis> {
+        self.nodes.get(node.index())
+    }
+
+    /// Chambers of the given class.
+    pub fn of_class(&self, class: DegreeClass) -> impl Iterator<Item = NodeIndex> + '_ {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter(move |(_, node)| node.degree == class)
+            .map(|(i, _)| NodeIndex::new(i))
+    }
+
+    /// The dead end furthest from the entrance.
+    pub fn deepest_dead_end(&self) -> Option<NodeIndex> {
+        self.of_class(DegreeClass::DeadEnd)
+            .filter(|node| Some(*node) != self.entrance)
+            .max_by_key(|node| self.nodes[node.index()].depth)
+    }
+
+    /// How many tunnels the furthest reachable chamber is from the entrance.
+    pub fn max_depth(&self) -> Option<usize> {
+        self.nodes.iter().filter_map(|node| node.depth).max()
+    }
+}
+
+/// Breadth-first search from `entrance`, counting tunnels.
+fn depths(graph: &UnGraph<CaveNode, CaveEdge>, entrance: NodeIndex) -> Vec<Option<usize>> {
+    let mut depths = vec![None; graph.node_count()];
+    let mut queue = VecDeque::from([entrance]);
+    depths[entrance.index()] = Some(0);
+    while let Some(node) = queue.pop_front() {
+        let next = depths[node.index()].map(|depth| depth + 1);
+        for neighbor in graph.neighbors(node) {
+            if depths[neighbor.index()].is_none() {
+                depths[neighbor.index()] = next;
+                queue.push_back(neighbor);
+            }
+        }
+    }
+    depths
+}
+
+/// Marks the chambers joined by at least one tunnel that isn't a bridge, found with Tarjan's
+/// lowlink depth-first search. Iterative, since a tree-like cave can be thousands of chambers deep.
+fn on_cycle(graph: &UnGraph<CaveNode, CaveEdge>) -> Vec<bool> {
+    let count = graph.node_count();
+    let mut order = vec![usize::MAX; count];
+    let mut low = vec![0; count];
+    let mut on_cycle = vec![false; count];
+    let mut visited = 0;
+    let incident = |node: NodeIndex| {
+        graph
+            .edges(node)
+            .map(|edge| (edge.id(), edge.target()))
+            .collect_vec()
+    };
+
+    for root in graph.node_indices() {
+        if order[root.index()] != usize::MAX {
+            continue;
+        }
+        order[root.index()] = visited;
+        low[root.index()] = visited;
+        visited += 1;
+        // (node, the tree edge it was reached through, its edges, the next edge to follow)
+        let mut stack = vec![(root, None, incident(root), 0)];
+        while let Some((node, parent_edge, edges, next)) = stack.last_mut() {
+            let node = *node;
+            if let Some((edge, neighbor)) = edges.get(*next).copied() {
+                *next += 1;
+                if Some(edge) == *parent_edge {
+                    continue;
+                }
+                if order[neighbor.index()] == usize::MAX {
+                    order[neighbor.index()] = visited;
+                    low[neighbor.index()] = visited;
+                    visited += 1;
+                    stack.push((neighbor, Some(edge), incident(neighbor), 0));
+                } else {
+                    // any edge back to a visited chamber closes a cycle
+                    low[node.index()] = low[node.index()].min(order[neighbor.index()]);
+                    if order[neighbor.index()] < order[node.index()] {
+                        on_cycle[node.index()] = true;
+                        on_cycle[neighbor.index()] = true;
+                    }
+                }
+                continue;
+            }
+
+            stack.pop();
+            if let Some((parent, ..)) = stack.last() {
+                let parent = parent.index();
+                low[parent] = low[parent].min(low[node.index()]);
+                // a tree edge is a bridge unless the subtree below it reaches back above it
+                if low[node.index()] <= order[parent] {
+                    on_cycle[parent] = true;
+                    on_cycle[node.index()] = true;
+                }
+            }
+        }
+    }
+    on_cycle
+}
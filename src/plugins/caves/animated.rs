@@ -0,0 +1,96 @@
+use crate::{
+    plugins::terrain::{animated::AnimatedKind, TileType},
+    prelude::*,
+};
+
+use super::{
+    analysis::{CaveAnalysis, DegreeClass},
+    generator::{CaveContext, CaveStage},
+    landmarks::standing_room,
+};
+
+/// Puts a chest in dead ends by [`Config::chest_chance`](super::Config), then torches on the
+/// ground and the sides of walls by [`Config::torch_chance`](super::Config), none closer than
+/// [`Config::animated_spacing`](super::Config) to another. Runs after mineralizing so nothing is
+/// put under water.
+pub struct AnimatedPlacer;
+
+impl CaveStage for AnimatedPlacer {
+    fn name(&self) -> &'static str {
+        "placing animated tiles"
+    }
+
+    fn step(&mut self, ctx: &mut CaveContext) -> bool {
+        let (width, height) = ctx.mask.dimensions();
+        let floor = |x: u32, y: u32| {
+            x < width
+                && y < height
+                && ctx.mask.get_pixel(x, y)[0] == 255
+                && ctx.tiles.get((y * width + x) as usize) != Some(&TileType::Water)
+        };
+        let spacing_squared = ctx.config.animated_spacing * ctx.config.animated_spacing;
+        let spaced = |placed: &[(UVec2, AnimatedKind)], tile: UVec2| {
+            placed.iter().all(|(other, _)| {
+                other.as_vec2().distance_squared(tile.as_vec2()) >= spacing_squared
+            })
+        };
+        let mut placed = vec![];
+        // presets and saves aren't checked like the sliders are
+        let chest_chance = ctx.config.chest_chance.clamp(0.0, 1.0) as f64;
+        let torch_chance = ctx.config.torch_chance.clamp(0.0, 1.0) as f64;
+
+        let openings = ctx
+            .landmarks
+            .entrance
+            .iter()
+            .chain(ctx.landmarks.exits.iter())
+            .map(|opening| opening.chamber)
+            .collect_vec();
+        let analysis = CaveAnalysis::new(&ctx.graph, CaveAnalysis::topmost(&ctx.graph));
+        for node in analysis.of_class(DegreeClass::DeadEnd) {
+            if openings.contains(&node) || !ctx.rng.gen_bool(chest_chance) {
+                continue;
+            }
+            let chamber = &ctx.graph[node];
+            let radius = (chamber.radius.ceil() * ctx.config.node_radius_factor).max(1.0);
+            let min = (chamber.position - radius).max(Vec2::ZERO).as_uvec2();
+            let max = (chamber.position + radius).as_uvec2();
+            let spot = (min.x..=max.x)
+                .cartesian_product(min.y..=max.y)
+                .map(|(x, y)| UVec2::new(x, y))
+                .filter(|tile| floor(tile.x, tile.y) && standing_room(&ctx.mask, *tile, 1))
+                .min_by(|a, b| {
+                    let a = a.as_vec2().distance_squared(chamber.position);
+                    let b = b.as_vec2().distance_squared(chamber.position);
+                    a.total_cmp(&b)
+                });
+            if let Some(tile) = spot.filter(|tile| spaced(&placed, *tile)) {
+                placed.push((tile, AnimatedKind::Chest));
+            }
+        }
+
+        for (y, x) in (0..height).cartesian_product(0..width) {
+            if !floor(x, y) {
+                continue;
+            }
+            let tile = UVec2::new(x, y);
+            let kind = if standing_room(&ctx.mask, tile, 2) {
+                AnimatedKind::StandingTorch
+            } else if y > 0
+                && floor(x, y - 1)
+                && floor(x, y + 1)
+                && (!floor(x.wrapping_sub(1), y) || !floor(x + 1, y))
+            {
+                AnimatedKind::WallTorch
+            } else {
+                continue;
+            };
+            if ctx.rng.gen_bool(torch_chance) && spaced(&placed, tile) {
+                placed.push((tile, kind));
+            }
+        }
+
+        ctx.animated = placed;
+        true
+    }
+}
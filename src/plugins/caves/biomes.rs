@@ -0,0 +1,169 @@
+use std::collections::VecDeque;
+
+use noise::{NoiseFn, Perlin};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    plugins::terrain::{Biome, TileBiome},
+    prelude::*,
+};
+
+use super::{
+    analysis::CaveAnalysis,
+    generator::{CaveContext, CaveStage},
+};
+
+/// What decides the biome of each chamber.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Reflect, Serialize, Deserialize)]
+pub enum BiomeSource {
+    /// Two low-frequency noise fields, moisture and minerals, sampled at each chamber.
+    #[default]
+    Noise,
+    /// Quarters of the way down from the topmost chamber to the deepest one, in tunnels, dry near
+    /// the surface and crystal deepest.
+    Depth,
+}
+
+/// How biomes shape the cave as it's generated.
+impl Biome {
+    /// Multiplies the width of tunnels between chambers of this biome.
+    pub fn tunnel_thickness(self) -> f32 {
+        match self {
+            Biome::Dry => 1.0,
+            Biome::Wet => 1.3,
+            Biome::Crystal => 0.8,
+            Biome::Guano => 1.1,
+        }
+    }
+
+    /// Smoothing iterations on top of [`Config::smoothing_iterations`](super::Config), water wears
+    /// walls round while crystal stays jagged.
+    pub fn extra_smoothing(self) -> isize {
+        match self {
+            Biome::Dry | Biome::Guano => 0,
+            Biome::Wet => 1,
+            Biome::Crystal => -1,
+        }
+    }
+
+    /// Chance of a floor tile against a wall being decorated.
+    pub fn decoration_chance(self) -> f64 {
+        match self {
+            Biome::Dry => 0.02,
+            Biome::Wet => 0.05,
+            Biome::Crystal => 0.15,
+            Biome::Guano => 0.2,
+        }
+    }
+}
+
+/// Gives every chamber a biome, then every tile the biome of the chamber it's closest to by a
+/// breadth-first flood from all chambers at once.
+pub struct BiomeZoning;
+
+impl CaveStage for BiomeZoning {
+    fn name(&self) -> &'static str {
+        "zoning biomes"
+    }
+
+    fn step(&mut self, ctx: &mut CaveContext) -> bool {
+        let biomes = match ctx.config.biome_source {
+            BiomeSource::Noise => {
+                let moisture = Perlin::new(ctx.rng.gen());
+                let minerals = Perlin::new(ctx.rng.gen());
+                let scale = ctx.config.biome_scale as f64;
+                ctx.graph
+                    .node_weights()
+                    .map(|node| {
+                        let point = node.position.as_dvec2().to_array().map(|v| v * scale);
+                        if minerals.get(point) > 0.25 {
+                            Biome::Crystal
+                        } else if moisture.get(point) > 0.1 {
+                            Biome::Wet
+                        } else if moisture.get(point) < -0.25 {
+                            Biome::Guano
+                        } else {
+                            Biome::Dry
+                        }
+                    })
+                    .collect_vec()
+            }
+            BiomeSource::Depth => {
+                let analysis = CaveAnalysis::new(&ctx.graph, CaveAnalysis::topmost(&ctx.graph));
+                let deepest = analysis.max_depth().unwrap_or(0) + 1;
+                let by_depth = [Biome::Dry, Biome::Guano, Biome::Wet, Biome::Crystal];
+                analysis
+                    .nodes
+                    .iter()
+                    .map(|node| {
+                        node.depth
+                            .map_or(Biome::Dry, |depth| by_depth[depth * 4 / deepest])
+                    })
+                    .collect_vec()
+            }
+        };
+        for (node, biome) in ctx.graph.node_weights_mut().zip(biomes) {
+            node.biome = biome;
+        }
+
+        let (width, height) = ctx.mask.dimensions();
+        let index = |x: u32, y: u32| (y * width + x) as usize;
+        let mut zones = vec![None; (width * height) as usize];
+        let mut queue = VecDeque::new();
+        for node in ctx.graph.node_weights() {
+            let tile = node.position.as_uvec2();
+            if tile.x < width && tile.y < height && zones[index(tile.x, tile.y)].is_none() {
+                zones[index(tile.x, tile.y)] = Some(node.biome);
+                queue.push_back((tile.x, tile.y));
+            }
+        }
+        while let Some((x, y)) = queue.pop_front() {
+            let biome = zones[index(x, y)];
+            let neighbors = [
+                (x.wrapping_sub(1), y),
+                (x + 1, y),
+                (x, y.wrapping_sub(1)),
+                (x, y + 1),
+            ];
+            for (nx, ny) in neighbors {
+                if nx < width && ny < height && zones[index(nx, ny)].is_none() {
+                    zones[index(nx, ny)] = biome;
+                    queue.push_back((nx, ny));
+                }
+            }
+        }
+        ctx.biomes = zones
+            .into_iter()
+            .map(|biome| TileBiome {
+                biome: biome.unwrap_or_default(),
+                decorated: false,
+            })
+            .collect();
+        true
+    }
+}
+
+/// Decorates floor tiles resting on or hanging from a wall, with their biome's
+/// [`Biome::decoration_chance`].
+pub struct Decorator;
+
+impl CaveStage for Decorator {
+    fn name(&self) -> &'static str {
+        "decorating"
+    }
+
+    fn step(&mut self, ctx: &mut CaveContext) -> bool {
+        let (width, height) = ctx.mask.dimensions();
+        let wall = |x: u32, y: u32| y >= height || ctx.mask.get_pixel(x, y)[0] == 0;
+        for (x, y, pixel) in ctx.mask.enumerate_pixels() {
+            let Some(tile) = ctx.biomes.get_mut((y * width + x) as usize) else {
+                continue;
+            };
+            let against_wall = (y > 0 && wall(x, y - 1)) || wall(x, y + 1);
+            if pixel[0] == 255 && against_wall {
+                tile.decorated = ctx.rng.gen_bool(tile.biome.decoration_chance());
+            }
+        }
+        true
+    }
+}
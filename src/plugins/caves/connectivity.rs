@@ -0,0 +1,281 @@
+use std::collections::{HashSet, VecDeque};
+
+use bevy::utils::tracing::instrument;
+use image::{GrayImage, Luma};
+use imageproc::region_labelling::{connected_components, Connectivity};
+use petgraph::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::prelude::*;
+
+use super::{connectors::Connector, CaveEdge, CaveNode, Config};
+
+/// Radius of the circles stamped along a tunnel dug into an unreachable pocket.
+const POCKET_TUNNEL_RADIUS: i32 = 2;
+
+/// What to do with floor regions that can't be reached from the main cave.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Reflect, Serialize, Deserialize)]
+pub enum PocketMode {
+    /// Fill the pocket back in with wall.
+    Remove,
+    /// Dig a tunnel from the pocket to the nearest reachable floor.
+    #[default]
+    Tunnel,
+}
+
+/// Summary of the last generation run, attached to the [`Caves`](super::Caves) entity.
+#[derive(Component, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CaveStats {
+    pub nodes: usize,
+    pub tree_edges: usize,
+    pub loop_edges: usize,
+    /// Floor regions that were disconnected from the main cave after rasterization.
+    pub pockets: usize,
+    pub pockets_removed: usize,
+    pub pockets_tunneled: usize,
+    pub floor_tiles: usize,
+}
+
+/// Replaces the edges of `graph` with their minimum spanning tree, plus [`Config::loop_fraction`]
+/// of the edges that the tree left out, so that every node is reachable. With
+/// [`Connector::MstLoops`] exactly [`Config::extra_loops`] of them are kept instead.
+#[instrument(skip_all)]
+pub fn span(
+    graph: &mut UnGraph<CaveNode, CaveEdge>,
+    config: &Config,
+    rng: &mut impl Rng,
+    stats: &mut CaveStats,
+) {
+    let candidates = graph
+        .edge_references()
+        .filter(|edge| edge.source() != edge.target())
+        .map(|edge| ordered(edge.source(), edge.target()))
+        .unique()
+        .collect_vec();
+    graph.clear_edges();
+
+    let tree = minimum_spanning_tree(graph, &candidates);
+    let tree_set: HashSet<_> = tree.iter().copied().collect();
+    let mut loops = candidates
+        .into_iter()
+        .filter(|edge| !tree_set.contains(edge))
+        .collect_vec();
+    loops.shuffle(rng);
+    let keep = match config.connector {
+        Connector::MstLoops => config.extra_loops,
+        _ => (loops.len() as f32 * config.loop_fraction).round() as usize,
+    };
+    loops.truncate(keep);
+
+    debug!("{} tree edges, {} loop edges", tree.len(), loops.len());
+    stats.nodes = graph.node_count();
+    stats.tree_edges = tree.len();
+    stats.loop_edges = loops.len();
+    for (a, b) in tree.into_iter().chain(loops) {
+        let edge = CaveEdge::between(&graph[a], &graph[b], config);
+        graph.add_edge(a, b, edge);
+    }
+}
+
+fn ordered(a: NodeIndex, b: NodeIndex) -> (NodeIndex, NodeIndex) {
+    if a < b {
+        (a, b)
+    } else {
+        (b, a)
+    }
+}
+
+/// Kruskal's algorithm over the `candidates`, shortest first. Nodes the candidates leave apart,
+/// which only [`Connector::Neighbors`] does, are joined by the shortest links between them as if
+/// every node were connected.
+fn minimum_spanning_tree(
+    graph: &UnGraph<CaveNode, CaveEdge>,
+    candidates: &[(NodeIndex, NodeIndex)],
+) -> Vec<(NodeIndex, NodeIndex)> {
+    let mut roots = (0..graph.node_count()).collect_vec();
+    let mut tree = Vec::with_capacity(graph.node_count().saturating_sub(1));
+    add_shortest(graph, candidates.to_vec(), &mut roots, &mut tree);
+    if tree.len() + 1 < graph.node_count() {
+        // the shortest link between two parts of the forest is always in this tree
+        let links = euclidean_spanning_tree(graph);
+        add_shortest(graph, links, &mut roots, &mut tree);
+    }
+    tree
+}
+
+/// Adds the shortest of `edges` that join two trees of the forest in `roots` to `tree`.
+fn add_shortest(
+    graph: &UnGraph<CaveNode, CaveEdge>,
+    mut edges: Vec<(NodeIndex, NodeIndex)>,
+    roots: &mut [usize],
+    tree: &mut Vec<(NodeIndex, NodeIndex)>,
+) {
+    fn root(roots: &mut [usize], mut i: usize) -> usize {
+        while roots[i] != i {
+            roots[i] = roots[roots[i]];
+            i = roots[i];
+        }
+        i
+    }
+    let length =
+        |(a, b): &(NodeIndex, NodeIndex)| graph[*a].position.distance_squared(graph[*b].position);
+    edges.sort_by(|a, b| length(a).total_cmp(&length(b)));
+    for (a, b) in edges {
+        let (root_a, root_b) = (root(roots, a.index()), root(roots, b.index()));
+        if root_a != root_b {
+            roots[root_a] = root_b;
+            tree.push((a, b));
+        }
+    }
+}
+
+/// Prim's algorithm over the complete graph of node positions.
+fn euclidean_spanning_tree(graph: &UnGraph<CaveNode, CaveEdge>) -> Vec<(NodeIndex, NodeIndex)> {
+    let positions = graph.node_weights().map(|node| node.position).collect_vec();
+    let mut tree = Vec::with_capacity(positions.len().saturating_sub(1));
+    if positions.is_empty() {
+        return tree;
+    }
+
+    let mut in_tree = vec![false; positions.len()];
+    // (squared distance to the tree, closest tree node) for every node not yet in the tree
+    let mut closest = vec![(f32::INFINITY, 0); positions.len()];
+    let mut current = 0;
+    in_tree[current] = true;
+    for _ in 1..positions.len() {
+        let mut next = None;
+        let mut next_distance = f32::INFINITY;
+        for (i, (done, closest)) in in_tree.iter().zip(closest.iter_mut()).enumerate() {
+            if *done {
+                continue;
+            }
+            let distance = positions[current].distance_squared(positions[i]);
+            if distance < closest.0 {
+                *closest = (distance, current);
+            }
+            if closest.0 < next_distance {
+                next_distance = closest.0;
+                next = Some(i);
+            }
+        }
+        let next = next.expect("unvisited node");
+        in_tree[next] = true;
+        tree.push(ordered(
+            NodeIndex::new(closest[next].1),
+            NodeIndex::new(next),
+        ));
+        current = next;
+    }
+    tree
+}
+
+/// Flood-fills the floor of `img` (255) and removes or tunnels into every region that isn't
+/// connected to the largest one, according to [`Config::pocket_mode`].
+#[instrument(skip_all)]
+pub fn connect_regions(img: &mut GrayImage, config: &Config, stats: &mut CaveStats) {
+    let labels = connected_components(img, Connectivity::Four, Luma([0u8]));
+    let region_count = labels.pixels().map(|label| label[0]).max().unwrap_or(0) as usize;
+    let mut areas = vec![0usize; region_count + 1];
+    for label in labels.pixels() {
+        areas[label[0] as usize] += 1;
+    }
+    // label 0 is wall
+    let Some((main, _)) = areas
+        .iter()
+        .enumerate()
+        .skip(1)
+        .max_by_key(|(_, area)| **area)
+    else {
+        stats.floor_tiles = 0;
+        return;
+    };
+
+    stats.pockets = region_count - 1;
+    debug!("{} unreachable pockets", stats.pockets);
+    match config.pocket_mode {
+        PocketMode::Remove => {
+            for (x, y, label) in labels.enumerate_pixels() {
+                if label[0] != 0 && label[0] as usize != main {
+                    img.put_pixel(x, y, Luma([0]));
+                }
+            }
+            stats.pockets_removed = stats.pockets;
+        }
+        PocketMode::Tunnel => {
+            let (width, height) = img.dimensions();
+            let index = |x: u32, y: u32| (y * width + x) as usize;
+
+            // breadth-first search outwards from the main region, remembering which main region
+            // tile each visited tile was reached from
+            let mut distance = vec![u32::MAX; (width * height) as usize];
+            let mut origin = vec![(0, 0); (width * height) as usize];
+            let mut queue = VecDeque::new();
+            for (x, y, label) in labels.enumerate_pixels() {
+                if label[0] as usize == main {
+                    distance[index(x, y)] = 0;
+                    origin[index(x, y)] = (x, y);
+                    queue.push_back((x, y));
+                }
+            }
+            while let Some((x, y)) = queue.pop_front() {
+                let next = distance[index(x, y)] + 1;
+                let neighbors = [
+                    (x.wrapping_sub(1), y),
+                    (x + 1, y),
+                    (x, y.wrapping_sub(1)),
+                    (x, y + 1),
+                ];
+                for (nx, ny) in neighbors {
+                    if nx < width && ny < height && distance[index(nx, ny)] == u32::MAX {
+                        distance[index(nx, ny)] = next;
+                        origin[index(nx, ny)] = origin[index(x, y)];
+                        queue.push_back((nx, ny));
+                    }
+                }
+            }
+
+            // the closest tile of every pocket to the main region
+            let mut closest: Vec<Option<(u32, (u32, u32))>> = vec![None; region_count + 1];
+            for (x, y, label) in labels.enumerate_pixels() {
+                let label = label[0] as usize;
+                if label == 0 || label == main {
+                    continue;
+                }
+                let d = distance[index(x, y)];
+                if closest[label].is_none_or(|(best, _)| d < best) {
+                    closest[label] = Some((d, (x, y)));
+                }
+            }
+
+            for (from, to) in closest
+                .into_iter()
+                .flatten()
+                .map(|(_, (x, y))| ((x, y), origin[index(x, y)]))
+            {
+                dig(img, from, to);
+                stats.pockets_tunneled += 1;
+            }
+        }
+    }
+
+    stats.floor_tiles = img.pixels().filter(|pixel| pixel[0] == 255).count();
+}
+
+fn dig(img: &mut GrayImage, from: (u32, u32), to: (u32, u32)) {
+    let from = UVec2::from(from).as_vec2();
+    let to = UVec2::from(to).as_vec2();
+    let steps = from.distance(to).ceil() as usize;
+    for i in 0..=steps {
+        let t = if steps == 0 {
+            0.0
+        } else {
+            i as f32 / steps as f32
+        };
+        imageproc::drawing::draw_filled_circle_mut(
+            img,
+            from.lerp(to, t).as_ivec2().into(),
+            POCKET_TUNNEL_RADIUS,
+            Luma([255]),
+        );
+    }
+}
@@ -0,0 +1,262 @@
+use flat_spatial::Grid;
+use petgraph::{prelude::*, visit::IntoNodeReferences};
+use serde::{Deserialize, Serialize};
+use spade::{DelaunayTriangulation, HasPosition, Point2, Triangulation};
+
+use crate::prelude::*;
+
+use super::{
+    generator::{CaveContext, CaveStage},
+    CaveEdge, CaveNode,
+};
+
+/// How placed rooms are connected into the graph the spanning tree and loops are picked from.
+///
+/// The minimum spanning tree is part of the Delaunay triangulation and of its Gabriel and relative
+/// neighborhood subgraphs, so those three give the same tree and only differ in which loops
+/// [`Config::loop_fraction`](super::Config) keeps. Delaunay is the default as its edges never
+/// cross.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Reflect, Serialize, Deserialize)]
+pub enum Connector {
+    /// Up to [`Config::edge_neighbors`](super::Config) rooms within each room's radius. Edges may
+    /// cross.
+    Neighbors,
+    #[default]
+    Delaunay,
+    /// Delaunay edges whose diametral circle holds no other room.
+    Gabriel,
+    /// Delaunay edges with no room closer to both ends than they are to each other.
+    RelativeNeighborhood,
+    /// The Delaunay triangulation, spanned with exactly
+    /// [`Config::extra_loops`](super::Config) loops instead of a fraction of them.
+    MstLoops,
+}
+
+impl Connector {
+    pub const ALL: [Connector; 5] = [
+        Connector::Neighbors,
+        Connector::Delaunay,
+        Connector::Gabriel,
+        Connector::RelativeNeighborhood,
+        Connector::MstLoops,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Connector::Neighbors => "neighbors",
+            Connector::Delaunay => "delaunay",
+            Connector::Gabriel => "gabriel",
+            Connector::RelativeNeighborhood => "relative neighborhood",
+            Connector::MstLoops => "mst + k loops",
+        }
+    }
+
+    /// A fresh stage connecting the rooms of `ctx` with this connector.
+    pub fn stage(self, _ctx: &CaveContext) -> Box<dyn CaveStage> {
+        match self {
+            Connector::Neighbors => Box::<NeighborConnector>::default(),
+            _ => Box::new(ProximityConnector::new(self)),
+        }
+    }
+}
+
+/// Connects each room to up to [`Config::edge_neighbors`](super::Config) rooms within its radius.
+#[derive(Default)]
+pub struct NeighborConnector {
+    grid: Option<Grid<NodeIndex, [f32; 2]>>,
+    next: usize,
+}
+
+impl CaveStage for NeighborConnector {
+    fn name(&self) -> &'static str {
+        "connecting rooms"
+    }
+
+    fn step(&mut self, ctx: &mut CaveContext) -> bool {
+        let graph = &mut ctx.graph;
+        let grid = self
+            .grid
+            .get_or_insert_with(|| room_grid(graph, ctx.config.grid_size));
+        if self.next >= graph.node_count() {
+            return true;
+        }
+
+        let node = NodeIndex::new(self.next);
+        let weight = &graph[node];
+        let neighbors = grid
+            .query_around([weight.position.x, weight.position.y], weight.radius)
+            .take(ctx.config.edge_neighbors)
+            .map(|(handle, _pos)| *grid.get(handle).unwrap().1)
+            .collect_vec();
+        for id in neighbors {
+            let edge = CaveEdge::between(&graph[node], &graph[id], &ctx.config);
+            graph.add_edge(node, id, edge);
+        }
+        self.next += 1;
+        self.next >= graph.node_count()
+    }
+}
+
+fn room_grid(graph: &UnGraph<CaveNode, CaveEdge>, cell: usize) -> Grid<NodeIndex, [f32; 2]> {
+    let mut grid = Grid::new(cell.max(1) as i32);
+    for (node, weight) in graph.node_references() {
+        grid.insert([weight.position.x, weight.position.y], node);
+    }
+    grid
+}
+
+struct Site {
+    position: Point2<f64>,
+    node: NodeIndex,
+}
+
+impl HasPosition for Site {
+    type Scalar = f64;
+
+    fn position(&self) -> Point2<f64> {
+        self.position
+    }
+}
+
+/// An edge of the Delaunay triangulation, with the rooms opposite it in its one or two triangles.
+struct Candidate {
+    a: NodeIndex,
+    b: NodeIndex,
+    a_position: Vec2,
+    b_position: Vec2,
+    opposite: Vec<Vec2>,
+}
+
+/// Triangulates the rooms, then keeps the Delaunay edges that pass [`Connector`]'s filter, one
+/// edge per step. Every subgraph of a Delaunay triangulation is planar and has no duplicate edges.
+pub struct ProximityConnector {
+    connector: Connector,
+    candidates: Option<Vec<Candidate>>,
+    /// Every room, for finding witnesses against relative neighborhood edges.
+    grid: Option<Grid<NodeIndex, [f32; 2]>>,
+    next: usize,
+}
+
+impl ProximityConnector {
+    pub fn new(connector: Connector) -> Self {
+        Self {
+            connector,
+            candidates: None,
+            grid: None,
+            next: 0,
+        }
+    }
+
+    fn keep(&self, ctx: &CaveContext, candidate: &Candidate) -> bool {
+        let (a, b) = (candidate.a_position, candidate.b_position);
+        match self.connector {
+            Connector::Neighbors | Connector::Delaunay | Connector::MstLoops => true,
+            // only the rooms opposite a Delaunay edge can fall inside its diametral circle first
+            Connector::Gabriel => {
+                let centre = a.midpoint(b);
+                let radius_squared = a.distance_squared(b) / 4.0;
+                candidate
+                    .opposite
+                    .iter()
+                    .all(|c| c.distance_squared(centre) >= radius_squared)
+            }
+            Connector::RelativeNeighborhood => {
+                let Some(grid) = self.grid.as_ref() else {
+                    return true;
+                };
+                let length_squared = a.distance_squared(b);
+                let centre = a.midpoint(b);
+                grid.query_around([centre.x, centre.y], length_squared.sqrt())
+                    .map(|(handle, _pos)| *grid.get(handle).unwrap().1)
+                    .filter(|node| *node != candidate.a && *node != candidate.b)
+                    .all(|node| {
+                        let c = ctx.graph[node].position;
+                        c.distance_squared(a).max(c.distance_squared(b)) >= length_squared
+                    })
+            }
+        }
+    }
+}
+
+impl CaveStage for ProximityConnector {
+    fn name(&self) -> &'static str {
+        "connecting rooms"
+    }
+
+    fn step(&mut self, ctx: &mut CaveContext) -> bool {
+        let Some(candidates) = self.candidates.as_ref() else {
+            self.candidates = Some(triangulate(ctx));
+            if self.connector == Connector::RelativeNeighborhood {
+                self.grid = Some(room_grid(&ctx.graph, ctx.config.grid_size));
+            }
+            return self.candidates.as_ref().is_some_and(Vec::is_empty);
+        };
+
+        let Some(candidate) = candidates.get(self.next) else {
+            return true;
+        };
+        if self.keep(ctx, candidate) {
+            let edge = CaveEdge::between(
+                &ctx.graph[candidate.a],
+                &ctx.graph[candidate.b],
+                &ctx.config,
+            );
+            ctx.graph.add_edge(candidate.a, candidate.b, edge);
+        }
+        self.next += 1;
+        self.next >= candidates.len()
+    }
+
+    fn draw(&self, gizmos: &mut Gizmos, to_world: &dyn Fn(Vec2) -> Vec2) {
+        let Some(candidates) = self.candidates.as_ref() else {
+            return;
+        };
+        for candidate in candidates.iter().skip(self.next) {
+            let a = to_world(candidate.a_position);
+            let b = to_world(candidate.b_position);
+            gizmos.line_2d(a, b, DARK_GRAY);
+        }
+    }
+}
+
+/// The edges of the Delaunay triangulation of every room, ordered by the rooms they join. Rooms
+/// sharing a position are merged into the first of them.
+fn triangulate(ctx: &CaveContext) -> Vec<Candidate> {
+    let sites = ctx
+        .graph
+        .node_references()
+        .map(|(node, weight)| Site {
+            position: Point2::new(weight.position.x as f64, weight.position.y as f64),
+            node,
+        })
+        .collect_vec();
+    let Ok(triangulation) = DelaunayTriangulation::<Site>::bulk_load_stable(sites) else {
+        return vec![];
+    };
+    let position = |point: Point2<f64>| Vec2::new(point.x as f32, point.y as f32);
+    triangulation
+        .undirected_edges()
+        .map(|edge| {
+            let [a, b] = edge.vertices();
+            let directed = edge.as_directed();
+            let opposite = [directed, directed.rev()]
+                .into_iter()
+                .filter_map(|edge| edge.opposite_vertex())
+                .map(|vertex| position(vertex.position()))
+                .collect();
+            let (a, b) = if a.data().node < b.data().node {
+                (a, b)
+            } else {
+                (b, a)
+            };
+            Candidate {
+                a: a.data().node,
+                b: b.data().node,
+                a_position: position(a.position()),
+                b_position: position(b.position()),
+                opposite,
+            }
+        })
+        .sorted_by_key(|candidate| (candidate.a, candidate.b))
+        .collect()
+}
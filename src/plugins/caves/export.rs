@@ -0,0 +1,112 @@
+use std::{error::Error, fmt::Write as _, fs, path::Path};
+
+use petgraph::{prelude::*, visit::IntoNodeReferences};
+
+use super::{CaveEdge, CaveNode};
+
+/// A file format a cave graph can be exported to, for inspecting its topology in external tools.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GraphFormat {
+    /// GraphViz, render with `neato -n` to keep the chambers where they are in the cave.
+    Dot,
+    GraphMl,
+}
+
+impl GraphFormat {
+    pub const ALL: [GraphFormat; 2] = [GraphFormat::Dot, GraphFormat::GraphMl];
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            GraphFormat::Dot => "dot",
+            GraphFormat::GraphMl => "graphml",
+        }
+    }
+
+    pub fn export(self, graph: &UnGraph<CaveNode, CaveEdge>) -> String {
+        match self {
+            GraphFormat::Dot => to_dot(graph),
+            GraphFormat::GraphMl => to_graphml(graph),
+        }
+    }
+
+    /// Writes `graph` to `path`, creating its directory if needed.
+    pub fn write(
+        self,
+        graph: &UnGraph<CaveNode, CaveEdge>,
+        path: &Path,
+    ) -> Result<(), Box<dyn Error>> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, self.export(graph))?;
+        Ok(())
+    }
+}
+
+/// Each chamber as a node with its position in tiles pinned by `pos` and its `radius`, and each
+/// tunnel as an edge with its `width`.
+pub fn to_dot(graph: &UnGraph<CaveNode, CaveEdge>) -> String {
+    let mut dot = String::from("graph cave {\n");
+    for (node, weight) in graph.node_references() {
+        writeln!(
+            dot,
+            "    {} [pos=\"{},{}!\", radius={}];",
+            node.index(),
+            weight.position.x,
+            weight.position.y,
+            weight.radius
+        )
+        .unwrap();
+    }
+    for edge in graph.edge_references() {
+        writeln!(
+            dot,
+            "    {} -- {} [width={}];",
+            edge.source().index(),
+            edge.target().index(),
+            edge.weight().width
+        )
+        .unwrap();
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+/// The same attributes as [`to_dot`], with the position split into `x` and `y` keys.
+pub fn to_graphml(graph: &UnGraph<CaveNode, CaveEdge>) -> String {
+    let mut xml = String::from(concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n",
+        "  <key id=\"x\" for=\"node\" attr.name=\"x\" attr.type=\"double\"/>\n",
+        "  <key id=\"y\" for=\"node\" attr.name=\"y\" attr.type=\"double\"/>\n",
+        "  <key id=\"radius\" for=\"node\" attr.name=\"radius\" attr.type=\"double\"/>\n",
+        "  <key id=\"width\" for=\"edge\" attr.name=\"width\" attr.type=\"double\"/>\n",
+        "  <graph id=\"cave\" edgedefault=\"undirected\">\n",
+    ));
+    for (node, weight) in graph.node_references() {
+        writeln!(
+            xml,
+            "    <node id=\"n{}\"><data key=\"x\">{}</data><data key=\"y\">{}</data>\
+             <data key=\"radius\">{}</data></node>",
+            node.index(),
+            weight.position.x,
+            weight.position.y,
+            weight.radius
+        )
+        .unwrap();
+    }
+    for edge in graph.edge_references() {
+        writeln!(
+            xml,
+            "    <edge id=\"e{}\" source=\"n{}\" target=\"n{}\">\
+             <data key=\"width\">{}</data></edge>",
+            edge.id().index(),
+            edge.source().index(),
+            edge.target().index(),
+            edge.weight().width
+        )
+        .unwrap();
+    }
+    xml.push_str("  </graph>\n</graphml>\n");
+    xml
+}
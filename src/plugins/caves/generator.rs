@@ -0,0 +1,189 @@
+use image::GrayImage;
+use petgraph::prelude::*;
+use rand::{rngs::StdRng, SeedableRng};
+
+use crate::{
+    plugins::terrain::{animated::AnimatedKind, TileBiome, TileType},
+    prelude::*,
+};
+
+use super::{
+    animated, biomes, connectivity::CaveStats, landmarks, landmarks::Landmarks, masks, minerals,
+    prefabs::Prefab, stages, CaveEdge, CaveNode, Config, GeneratedCave,
+};
+
+/// Everything the stages of a [`CavePipeline`] share while generating one cave.
+pub struct CaveContext {
+    pub size: Vec2,
+    pub config: Config,
+    /// The single RNG every stage draws from, seeded by [`Config::seed`].
+    pub rng: StdRng,
+    pub graph: UnGraph<CaveNode, CaveEdge>,
+    /// The rasterized tiles, 255 for floor and 0 for wall.
+    pub mask: GrayImage,
+    pub stats: CaveStats,
+    pub landmarks: Landmarks,
+    /// [`Config::seed_mask`], stretched over the map.
+    pub seed_mask: Option<GrayImage>,
+    /// [`Config::prefabs`] that loaded.
+    pub prefabs: Vec<Prefab>,
+    /// The biome of each tile, row by row like the mask. Empty until zoned.
+    pub biomes: Vec<TileBiome>,
+    /// The kind of each tile, row by row like the mask. Empty until mineralized.
+    pub tiles: Vec<TileType>,
+    pub animated: Vec<(UVec2, AnimatedKind)>,
+}
+
+impl CaveContext {
+    pub fn new(size: Vec2, config: &Config) -> Self {
+        let seed_mask = config.seed_mask.as_ref().and_then(|name| {
+            masks::load_mask(name, config.mask_threshold, Some(size.as_uvec2()))
+                .inspect_err(|err| warn!("failed to load seed mask {name}: {err}"))
+                .ok()
+        });
+        Self {
+            size,
+            config: config.clone(),
+            rng: StdRng::seed_from_u64(config.seed),
+            graph: UnGraph::default(),
+            mask: GrayImage::new(size.x as u32, size.y as u32),
+            stats: CaveStats::default(),
+            landmarks: Landmarks::default(),
+            seed_mask,
+            prefabs: Prefab::load_all(&config.prefabs),
+            biomes: vec![],
+            tiles: vec![],
+            animated: vec![],
+        }
+    }
+
+    /// Whether a room may be placed at `position`, anywhere on the floor of the seed mask if there
+    /// is one.
+    pub fn allows(&self, position: Vec2) -> bool {
+        let Some(mask) = self.seed_mask.as_ref() else {
+            return true;
+        };
+        let position = position.as_uvec2();
+        position.x < mask.width()
+            && position.y < mask.height()
+            && mask.get_pixel(position.x, position.y)[0] == 255
+    }
+}
+
+/// One step of cave generation, such as placing nodes, connecting them or carving tunnels. A stage
+/// works in small units so generation can be paused between them to watch the cave form.
+pub trait CaveStage: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Does the next unit of work, returning whether the stage is finished.
+    fn step(&mut self, ctx: &mut CaveContext) -> bool;
+
+    /// Draws any work in progress that isn't in the graph or the mask yet.
+    fn draw(&self, _gizmos: &mut Gizmos, _to_world: &dyn Fn(Vec2) -> Vec2) {}
+}
+
+/// Creates a fresh stage for every cave generated.
+pub type StageFactory = fn(&CaveContext) -> Box<dyn CaveStage>;
+
+/// The stages every cave is generated with, in order.
+#[derive(Resource, Clone)]
+pub struct CavePipeline(pub Vec<StageFactory>);
+
+impl Default for CavePipeline {
+    fn default() -> Self {
+        Self(vec![
+            |ctx| ctx.config.placer.stage(ctx),
+            |_| Box::<stages::PrefabPicker>::default(),
+            |ctx| ctx.config.connector.stage(ctx),
+            |_| Box::new(stages::SpanningTree),
+            |_| Box::new(biomes::BiomeZoning),
+            |_| Box::<stages::RoomRasterizer>::default(),
+            |_| Box::<stages::TunnelCarver>::default(),
+            |_| Box::new(stages::Smoothing),
+            |_| Box::new(stages::PrefabStamper),
+            |_| Box::new(stages::RegionConnector),
+            |_| Box::<landmarks::LandmarkPlacer>::default(),
+            |_| Box::new(biomes::Decorator),
+            |_| Box::new(minerals::Mineralizer),
+            |_| Box::new(animated::AnimatedPlacer),
+        ])
+    }
+}
+
+/// A cave part way through a [`CavePipeline`], run to completion on a task or advanced by hand.
+pub struct Generator {
+    pub ctx: CaveContext,
+    stages: Vec<Box<dyn CaveStage>>,
+    /// Index of the stage currently running.
+    current: usize,
+}
+
+impl Generator {
+    pub fn new(size: Vec2, config: &Config, pipeline: &CavePipeline) -> Self {
+        let ctx = CaveContext::new(size, config);
+        let stages = pipeline.0.iter().map(|factory| factory(&ctx)).collect();
+        Self {
+            ctx,
+            stages,
+            current: 0,
+        }
+    }
+
+    pub fn stage_names(&self) -> Vec<&'static str> {
+        self.stages.iter().map(|stage| stage.name()).collect()
+    }
+
+    /// Index of the stage currently running, the number of stages once done.
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    pub fn stage_name(&self) -> &'static str {
+        self.stages
+            .get(self.current)
+            .map_or("done", |stage| stage.name())
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.current >= self.stages.len()
+    }
+
+    /// Does the next unit of work of the current stage, moving on to the next stage once it's
+    /// finished.
+    pub fn step(&mut self) {
+        let Some(stage) = self.stages.get_mut(self.current) else {
+            return;
+        };
+        if stage.step(&mut self.ctx) {
+            self.current += 1;
+            debug!("{}", self.stage_name());
+        }
+    }
+
+    /// Steps until the current stage is over.
+    pub fn finish_stage(&mut self) {
+        let current = self.current;
+        while self.current == current && !self.is_done() {
+            self.step();
+        }
+    }
+
+    pub fn draw(&self, gizmos: &mut Gizmos, to_world: &dyn Fn(Vec2) -> Vec2) {
+        if let Some(stage) = self.stages.get(self.current) {
+            stage.draw(gizmos, to_world);
+        }
+    }
+
+    /// Takes the finished cave out of the generator.
+    pub fn take(&mut self) -> GeneratedCave {
+        GeneratedCave {
+            graph: std::mem::take(&mut self.ctx.graph),
+            mask: std::mem::take(&mut self.ctx.mask),
+            stats: std::mem::take(&mut self.ctx.stats),
+            landmarks: std::mem::take(&mut self.ctx.landmarks),
+            biomes: std::mem::take(&mut self.ctx.biomes),
+            tiles: std::mem::take(&mut self.ctx.tiles),
+            animated: std::mem::take(&mut self.ctx.animated),
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use image::{GrayImage, Luma};
+use noise::Perlin;
+use petgraph::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::prelude::*;
+
+use super::{
+    analysis::{CaveAnalysis, DegreeClass},
+    generator::{CaveContext, CaveStage},
+    stages,
+};
+
+/// A tunnel from a chamber out through the edge of the map.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Opening {
+    pub chamber: NodeIndex,
+    /// The border tile the tunnel breaks through.
+    pub tile: UVec2,
+}
+
+/// Where a cave is entered and left, and where creatures can be placed in it.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Landmarks {
+    /// Breaks through the top of the map, from the chamber nearest the surface.
+    pub entrance: Option<Opening>,
+    /// Break through the sides or bottom of the map, from the deepest dead ends.
+    pub exits: Vec<Opening>,
+    /// Floor tiles with [`Config::spawn_headroom`](super::Config) tiles of open space above them.
+    pub spawn_points: Vec<UVec2>,
+}
+
+/// Where a cave is entered from the surface, a child of its [`Caves`](super::Caves).
+#[derive(Component)]
+#[require(Transform)]
+pub struct Entrance;
+
+/// Where a cave can be left, a child of its [`Caves`](super::Caves).
+#[derive(Component)]
+#[require(Transform)]
+pub struct Exit;
+
+/// Somewhere to put a creature down, a child of its [`Caves`](super::Caves).
+#[derive(Component)]
+#[require(Transform)]
+pub struct SpawnPoint;
+
+/// Carves the entrance, then each exit, then picks the spawn points, one per step. Runs once the
+/// mask is smoothed and its regions connected, so the openings aren't smoothed shut against the
+/// border. The [`Mineralizer`](super::minerals::Mineralizer) runs later and keeps water off the
+/// spawn points.
+#[derive(Default)]
+pub struct LandmarkPlacer {
+    noise: Option<Perlin>,
+    next: usize,
+}
+
+impl CaveStage for LandmarkPlacer {
+    fn name(&self) -> &'static str {
+        "placing entrances"
+    }
+
+    fn step(&mut self, ctx: &mut CaveContext) -> bool {
+        let noise = *self.noise.get_or_insert_with(|| Perlin::new(ctx.rng.gen()));
+        let exits = ctx.config.exits;
+        match self.next {
+            0 => {
+                let topmost = ctx
+                    .graph
+                    .node_indices()
+                    .filter(|node| is_floor(ctx, *node))
+                    .max_by(|a, b| {
+                        let (a, b) = (ctx.graph[*a].position, ctx.graph[*b].position);
+                        a.y.total_cmp(&b.y)
+                    });
+                if let Some(chamber) = topmost {
+                    let position = ctx.graph[chamber].position;
+                    let tile = UVec2::new(position.x as u32, ctx.mask.height() - 1);
+                    ctx.landmarks.entrance = Some(carve(ctx, chamber, tile, &noise));
+                }
+            }
+            next if next <= exits => {
+                if let Some(chamber) = next_exit(ctx) {
+                    let tile = nearest_border(&ctx.mask, ctx.graph[chamber].position);
+                    let exit = carve(ctx, chamber, tile, &noise);
+                    ctx.landmarks.exits.push(exit);
+                }
+            }
+            _ => {
+                ctx.landmarks.spawn_points = spawn_points(ctx);
+                ctx.stats.floor_tiles = ctx.mask.pixels().filter(|pixel| pixel[0] == 255).count();
+                return true;
+            }
+        }
+        self.next += 1;
+        false
+    }
+}
+
+/// Whether a chamber's centre survived smoothing and pocket removal.
+fn is_floor(ctx: &CaveContext, node: NodeIndex) -> bool {
+    let position = ctx.graph[node].position.as_uvec2();
+    position.x < ctx.mask.width()
+        && position.y < ctx.mask.height()
+        && ctx.mask.get_pixel(position.x, position.y)[0] == 255
+}
+
+fn carve(ctx: &mut CaveContext, chamber: NodeIndex, tile: UVec2, noise: &Perlin) -> Opening {
+    let from = ctx.graph[chamber].position;
+    // aim past the border so the end of the tunnel isn't tapered shut
+    let to =
+        tile.as_vec2() + (tile.as_vec2() - from).normalize_or_zero() * ctx.config.opening_width;
+    stages::tunnel(
+        from,
+        to,
+        ctx.config.opening_width,
+        &mut ctx.mask,
+        &ctx.config,
+        noise,
+    );
+    Opening { chamber, tile }
+}
+
+/// The deepest dead end that isn't already an opening.
+fn next_exit(ctx: &CaveContext) -> Option<NodeIndex> {
+    let analysis = CaveAnalysis::new(
+        &ctx.graph,
+        ctx.landmarks.entrance.map(|entrance| entrance.chamber),
+    );
+    let taken = ctx
+        .landmarks
+        .entrance
+        .iter()
+        .chain(ctx.landmarks.exits.iter())
+        .map(|opening| opening.chamber)
+        .collect_vec();
+    analysis
+        .of_class(DegreeClass::DeadEnd)
+        .filter(|node| !taken.contains(node) && is_floor(ctx, *node))
+        .max_by_key(|node| analysis.nodes[node.index()].depth)
+}
+
+/// The closest tile on the left, right or bottom edge of the map. The top is left to the entrance.
+fn nearest_border(mask: &GrayImage, position: Vec2) -> UVec2 {
+    let (width, height) = mask.dimensions();
+    let position = position.as_uvec2().min(UVec2::new(width - 1, height - 1));
+    [
+        (position.x, UVec2::new(0, position.y)),
+        (width - 1 - position.x, UVec2::new(width - 1, position.y)),
+        (position.y, UVec2::new(position.x, 0)),
+    ]
+    .into_iter()
+    .min_by_key(|(distance, _)| *distance)
+    .map(|(_, tile)| tile)
+    .unwrap()
+}
+
+/// One standing spot per chamber, deepest chambers first, up to
+/// [`Config::spawn_points`](super::Config) of them at least `spawn_spacing` apart. The spot nearest
+/// the chamber's centre is used.
+fn spawn_points(ctx: &CaveContext) -> Vec<UVec2> {
+    let spacing_squared = ctx.config.spawn_spacing * ctx.config.spawn_spacing;
+    let mut points: Vec<UVec2> = vec![];
+    let entrance = ctx.landmarks.entrance.map(|entrance| entrance.chamber);
+    let analysis = CaveAnalysis::new(&ctx.graph, entrance);
+    let candidates = ctx
+        .graph
+        .node_indices()
+        .filter(|node| Some(*node) != entrance)
+        .filter(|node| analysis.nodes[node.index()].depth.is_some())
+        .sorted_by_key(|node| std::cmp::Reverse(analysis.nodes[node.index()].depth))
+        .filter_map(|node| {
+            let chamber = &ctx.graph[node];
+            let radius = (chamber.radius.ceil() * ctx.config.node_radius_factor).max(1.0);
+            let min = (chamber.position - radius).max(Vec2::ZERO).as_uvec2();
+            let max = (chamber.position + radius).as_uvec2();
+            (min.x..=max.x)
+                .cartesian_product(min.y..=max.y)
+                .map(|(x, y)| UVec2::new(x, y))
+                .filter(|tile| standing_room(&ctx.mask, *tile, ctx.config.spawn_headroom))
+                .min_by(|a, b| {
+                    let a = a.as_vec2().distance_squared(chamber.position);
+                    let b = b.as_vec2().distance_squared(chamber.position);
+                    a.total_cmp(&b)
+                })
+        });
+    for tile in candidates {
+        if points.len() >= ctx.config.spawn_points {
+            break;
+        }
+        let spaced = points
+            .iter()
+            .all(|point| point.as_vec2().distance_squared(tile.as_vec2()) >= spacing_squared);
+        if spaced {
+            points.push(tile);
+        }
+    }
+    points
+}
+
+/// Whether `tile` is floor resting on wall, with `headroom` floor tiles above it. The bottom of the
+/// map doesn't count as wall, an exit may open through it.
+pub(super) fn standing_room(mask: &GrayImage, tile: UVec2, headroom: u32) -> bool {
+    let floor = |x: u32, y: u32| {
+        x < mask.width() && y < mask.height() && *mask.get_pixel(x, y) == Luma([255])
+    };
+    tile.y > 0
+        && !floor(tile.x, tile.y - 1)
+        && (tile.y..=tile.y + headroom).all(|y| floor(tile.x, y))
+}
@@ -0,0 +1,72 @@
+use std::{error::Error, fs, path::Path};
+
+use image::{imageops, GrayImage, Luma};
+
+use crate::prelude::*;
+
+/// Hand-drawn masks live in `assets/` under this folder, one grayscale PNG per cave.
+pub const MASK_DIR: &str = "cave_masks";
+
+/// The PNGs in `assets/`[`MASK_DIR`], sorted by name.
+pub fn available_masks() -> Vec<String> {
+    let Ok(entries) = fs::read_dir(Path::new("assets").join(MASK_DIR)) else {
+        return vec![];
+    };
+    let mut names: Vec<String> = entries
+        .flatten()
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| name.ends_with(".png"))
+        .collect();
+    names.sort();
+    names
+}
+
+/// Reads the named mask as tiles, 255 for floor and 0 for wall, see [`image_to_mask`].
+pub fn load_mask(
+    name: &str,
+    threshold: u8,
+    size: Option<UVec2>,
+) -> Result<GrayImage, Box<dyn Error>> {
+    let path = Path::new("assets").join(MASK_DIR).join(name);
+    Ok(image_to_mask(
+        image::open(&path)?.into_luma8(),
+        threshold,
+        size,
+    ))
+}
+
+/// Tiles from a mask image. Pixels at least `threshold` bright are floor. The image is flipped so
+/// its top row becomes the top of the map, and stretched to `size` if given.
+pub fn image_to_mask(mut image: GrayImage, threshold: u8, size: Option<UVec2>) -> GrayImage {
+    if let Some(size) = size.filter(|size| *size != UVec2::from(image.dimensions())) {
+        image = imageops::resize(&image, size.x, size.y, imageops::FilterType::Triangle);
+    }
+    imageops::flip_vertical_in_place(&mut image);
+    for pixel in image.pixels_mut() {
+        *pixel = Luma([if pixel[0] >= threshold { 255 } else { 0 }]);
+    }
+    image
+}
+
+/// The image of a cave's tiles, top row first, the way [`image_to_mask`] reads it back.
+pub fn mask_to_image(mask: &GrayImage) -> GrayImage {
+    imageops::flip_vertical(mask)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn masks_round_trip_through_images() {
+        // floor in the bottom left corner only, so any flip shows
+        let mask = GrayImage::from_fn(5, 3, |x, y| Luma([if x < 2 && y == 0 { 255 } else { 0 }]));
+        let image = mask_to_image(&mask);
+        assert_eq!(
+            image.get_pixel(0, 2)[0],
+            255,
+            "bottom row of the map is the last in the image"
+        );
+        assert_eq!(image_to_mask(image, 128, None), mask);
+    }
+}
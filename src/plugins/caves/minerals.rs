@@ -0,0 +1,111 @@
+use noise::{NoiseFn, Perlin};
+
+use crate::{
+    plugins::terrain::{Biome, TileType},
+    prelude::*,
+};
+
+use super::generator::{CaveContext, CaveStage};
+
+/// Turns the mask into tiles of every [`TileType`], by biome and noise. Runs after the landmarks
+/// are placed and the floor decorated, once the shape of the cave is settled, and before the
+/// [`AnimatedPlacer`](super::animated::AnimatedPlacer), which needs the water.
+pub struct Mineralizer;
+
+impl CaveStage for Mineralizer {
+    fn name(&self) -> &'static str {
+        "mineralizing"
+    }
+
+    fn step(&mut self, ctx: &mut CaveContext) -> bool {
+        let (width, height) = ctx.mask.dimensions();
+        let index = |x: u32, y: u32| (y * width + x) as usize;
+        let biome = |x: u32, y: u32| {
+            ctx.biomes
+                .get(index(x, y))
+                .map_or(Biome::default(), |tile| tile.biome)
+        };
+        let floor = |x: u32, y: u32| x < width && y < height && ctx.mask.get_pixel(x, y)[0] == 255;
+        let spawn_point = |x: u32, y: u32| ctx.landmarks.spawn_points.contains(&UVec2::new(x, y));
+        let soft_rock = Perlin::new(ctx.rng.gen());
+        let scale = ctx.config.soft_rock_scale as f64;
+
+        let mut tiles = vec![TileType::Wall; (width * height) as usize];
+        for y in 0..height {
+            for x in 0..width {
+                let tile = &mut tiles[index(x, y)];
+                if floor(x, y) {
+                    *tile = TileType::Floor;
+                    continue;
+                }
+                let border = x == 0 || y == 0 || x == width - 1 || y == height - 1;
+                let exposed = floor(x.wrapping_sub(1), y)
+                    || floor(x + 1, y)
+                    || floor(x, y.wrapping_sub(1))
+                    || floor(x, y + 1);
+                *tile = if border {
+                    TileType::Bedrock
+                } else if biome(x, y) == Biome::Crystal
+                    && exposed
+                    && ctx
+                        .rng
+                        .gen_bool(ctx.config.ore_chance.clamp(0.0, 1.0) as f64)
+                {
+                    TileType::Ore
+                } else if soft_rock.get([x as f64 * scale, y as f64 * scale])
+                    > ctx.config.soft_rock_threshold as f64
+                {
+                    TileType::SoftRock
+                } else {
+                    TileType::Wall
+                };
+            }
+        }
+
+        // guano heaps on the ground under roosts
+        for y in 1..height {
+            for x in 0..width {
+                if tiles[index(x, y)] == TileType::Floor
+                    && biome(x, y) == Biome::Guano
+                    && !floor(x, y - 1)
+                {
+                    tiles[index(x, y)] = TileType::Guano;
+                }
+            }
+        }
+
+        // wet basins fill a layer at a time, a run of floor only holds water if it's walled in at
+        // both ends, resting on wall or water all the way along and clear of spawn points
+        for _ in 0..ctx.config.water_depth {
+            let mut pools = vec![];
+            for y in 1..height {
+                let mut x = 0;
+                while x < width {
+                    if tiles[index(x, y)] != TileType::Floor {
+                        x += 1;
+                        continue;
+                    }
+                    let start = x;
+                    while x < width && tiles[index(x, y)] == TileType::Floor {
+                        x += 1;
+                    }
+                    let walled = start > 0 && !floor(start - 1, y) && x < width && !floor(x, y);
+                    let held = (start..x).all(|x| {
+                        biome(x, y) == Biome::Wet
+                            && (!floor(x, y - 1) || tiles[index(x, y - 1)] == TileType::Water)
+                            && !spawn_point(x, y)
+                    });
+                    if walled && held {
+                        pools.extend((start..x).map(|x| index(x, y)));
+                    }
+                }
+            }
+            for i in pools {
+                tiles[i] = TileType::Water;
+            }
+        }
+
+        ctx.tiles = tiles;
+        true
+    }
+}
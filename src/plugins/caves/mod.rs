@@ -0,0 +1,1204 @@
+use std::path::Path;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+use std::time::Duration;
+
+use analysis::{CaveAnalysis, DegreeClass};
+use biomes::BiomeSource;
+use connectivity::{CaveStats, PocketMode};
+use connectors::Connector;
+use export::GraphFormat;
+
+use crate::{
+    plugins::terrain::{TileType, FLOOR, WALL},
+    prelude::*,
+};
+use bevy::{
+    color::ColorCurve,
+    input::common_conditions::input_just_pressed,
+    tasks::{block_on, futures_lite::future, AsyncComputeTaskPool, Task},
+};
+use generator::{CavePipeline, Generator};
+use image::{GrayImage, Luma};
+use landmarks::{Entrance, Exit, Landmarks, SpawnPoint};
+use masks::available_masks;
+use petgraph::prelude::*;
+use placers::Placer;
+use prefabs::available_prefabs;
+use presets::PresetUi;
+use rand::thread_rng;
+use save::{saved_caves, SavedCave, SAVE_DIR};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    pathfinding::{DMap, UpdateDMap},
+    terrain::{
+        animated::{AnimatedKind, AnimatedLayer, SetAnimated},
+        coords::TerrainSpace,
+        Biome, MapConfig, SetBiomes, SetTiles, TileBiome,
+    },
+};
+
+pub mod analysis;
+pub mod animated;
+pub mod biomes;
+pub mod connectivity;
+pub mod connectors;
+pub mod export;
+pub mod generator;
+pub mod landmarks;
+pub mod masks;
+pub mod minerals;
+pub mod placers;
+pub mod prefabs;
+pub mod presets;
+pub mod save;
+pub mod smoothing;
+pub mod stages;
+
+pub fn caves_plugin(app: &mut App) {
+    app.insert_resource(Config {
+        seed: thread_rng().gen(),
+        ..default()
+    });
+    app.add_plugins(presets::presets_plugin);
+    app.init_resource::<CavePipeline>();
+    app.init_resource::<CaveAnalysis>();
+    app.init_resource::<CaveDebug>();
+    app.add_event::<Regen>();
+    app.add_event::<Step>();
+    app.add_event::<SaveCave>();
+    app.add_event::<LoadCave>();
+    app.add_event::<ExportGraph>();
+    app.add_event::<ImportMask>();
+    app.add_systems(Update, ui);
+    app.add_systems(
+        Update,
+        (
+            reseed.run_if(input_just_pressed(KeyCode::Space)),
+            regen.run_if(on_event::<Regen>),
+        )
+            .chain(),
+    );
+    app.add_systems(
+        Update,
+        (
+            save_cave.run_if(on_event::<SaveCave>),
+            load_cave.run_if(on_event::<LoadCave>),
+            export_graph.run_if(on_event::<ExportGraph>),
+            import_mask.run_if(on_event::<ImportMask>),
+        ),
+    );
+    app.add_systems(
+        Update,
+        (
+            start_generation,
+            step_generation.run_if(on_event::<Step>),
+            finish_generation,
+        )
+            .chain(),
+    );
+    app.add_systems(
+        Update,
+        draw_graph.run_if(|debug: Res<CaveDebug>| debug.draw_graph),
+    );
+    app.add_systems(FixedUpdate, insert_dmap);
+}
+
+#[allow(clippy::too_many_arguments)]
+fn ui(
+    mut contexts: EguiContexts,
+    mut config: ResMut<Config>,
+    mut presets: PresetUi,
+    mut map: ResMut<MapConfig>,
+    mut debug: ResMut<CaveDebug>,
+    mut events: EventWriter<Regen>,
+    mut steps: EventWriter<Step>,
+    mut saves: EventWriter<SaveCave>,
+    mut loads: EventWriter<LoadCave>,
+    mut exports: EventWriter<ExportGraph>,
+    mut imports: EventWriter<ImportMask>,
+    stats: Query<&CaveStats, Without<Generating>>,
+    analysis: Res<CaveAnalysis>,
+    tasks: Query<&GenerateTask>,
+    stepping: Query<&Stepping>,
+) {
+    egui::Window::new("Caves").show(contexts.ctx_mut(), |ui| {
+        let mut regen = false;
+        // edit a copy so the tilemap is only resized once a drag has finished
+        let mut size = map.size;
+        let width = ui.add(egui::Slider::new(&mut size.x, 16..=1024).text("map width"));
+        let height = ui.add(egui::Slider::new(&mut size.y, 16..=1024).text("map height"));
+        if width.drag_stopped() || height.drag_stopped() {
+            map.size = size;
+            regen = true;
+        }
+        ui.horizontal(|ui| {
+            let seed = ui.add(egui::DragValue::new(&mut config.seed).prefix("seed "));
+            regen |= seed.drag_stopped() || (seed.changed() && !seed.dragged());
+            if ui.button("randomize").clicked() {
+                config.seed = thread_rng().gen();
+                regen = true;
+            }
+        });
+        regen |= presets.show(ui, &mut config);
+        egui::ComboBox::from_label("placer")
+            .selected_text(config.placer.name())
+            .show_ui(ui, |ui| {
+                for placer in Placer::ALL {
+                    regen |= ui
+                        .selectable_value(&mut config.placer, placer, placer.name())
+                        .changed();
+                }
+            });
+        match config.placer {
+            Placer::Bsp => {
+                regen |= ui
+                    .add(egui::Slider::new(&mut config.min_area, 1.0..=255.0).text("min node area"))
+                    .drag_stopped();
+                regen |= ui
+                    .add(
+                        egui::Slider::new(&mut config.bsp_split_jitter, 0.0..=0.9)
+                            .text("split jitter"),
+                    )
+                    .drag_stopped();
+            }
+            Placer::PoissonDisk => {
+                regen |= ui
+                    .add(
+                        egui::Slider::new(&mut config.min_node_spacing, 1.0..=64.0)
+                            .text("min node spacing"),
+                    )
+                    .drag_stopped();
+                regen |= ui
+                    .add(
+                        egui::Slider::new(&mut config.max_node_spacing, 1.0..=64.0)
+                            .text("max node spacing"),
+                    )
+                    .drag_stopped();
+            }
+            Placer::JitteredGrid => {
+                regen |= ui
+                    .add(
+                        egui::Slider::new(&mut config.jitter_cell_size, 1.0..=64.0)
+                            .text("grid cell size"),
+                    )
+                    .drag_stopped();
+                regen |= ui
+                    .add(egui::Slider::new(&mut config.grid_jitter, 0.0..=1.0).text("grid jitter"))
+                    .drag_stopped();
+            }
+        }
+        egui::ComboBox::from_label("connector")
+            .selected_text(config.connector.name())
+            .show_ui(ui, |ui| {
+                for connector in Connector::ALL {
+                    regen |= ui
+                        .selectable_value(&mut config.connector, connector, connector.name())
+                        .changed();
+                }
+            });
+        if config.connector == Connector::Neighbors {
+            regen |= ui
+                .add(egui::Slider::new(&mut config.grid_size, 1..=128).text("grid size"))
+                .drag_stopped();
+            regen |= ui
+                .add(egui::Slider::new(&mut config.edge_neighbors, 1..=10).text("edge neighbors"))
+                .drag_stopped();
+        }
+        regen |= ui
+            .add(egui::Slider::new(&mut config.tunnel_segments, 1..=20).text("tunnel segments"))
+            .drag_stopped();
+        regen |= ui
+            .add(egui::Slider::new(&mut config.tunnel_meander, 0.0..=32.0).text("tunnel meander"))
+            .drag_stopped();
+        regen |= ui
+            .add(
+                egui::Slider::new(&mut config.tunnel_meander_scale, 0.005..=0.5)
+                    .logarithmic(true)
+                    .text("tunnel meander scale"),
+            )
+            .drag_stopped();
+        regen |= ui
+            .add(egui::Slider::new(&mut config.tunnel_taper, 0.0..=1.0).text("tunnel taper"))
+            .drag_stopped();
+        regen |= ui
+            .add(
+                egui::Slider::new(&mut config.tunnel_thickness, 0.01..=1.0)
+                    .text("tunnel thickness"),
+            )
+            .drag_stopped();
+        regen |= ui
+            .add(
+                egui::Slider::new(&mut config.node_radius_factor, 0.01..=1.0)
+                    .text("node radius factor"),
+            )
+            .drag_stopped();
+        regen |= ui
+            .add(
+                egui::Slider::new(&mut config.node_color_factor, 1.0..=512.0)
+                    .text("node color factor"),
+            )
+            .drag_stopped();
+        regen |= ui
+            .add(
+                egui::Slider::new(&mut config.edge_color_factor, 1.0..=512.0)
+                    .text("edge color factor"),
+            )
+            .drag_stopped();
+        regen |= ui
+            .add(
+                egui::Slider::new(&mut config.trunc_falloff_factor, 0.01..=1.0)
+                    .text("trunc falloff factor"),
+            )
+            .drag_stopped();
+        if config.connector == Connector::MstLoops {
+            regen |= ui
+                .add(egui::Slider::new(&mut config.extra_loops, 0..=64).text("extra loops"))
+                .drag_stopped();
+        } else {
+            regen |= ui
+                .add(egui::Slider::new(&mut config.loop_fraction, 0.0..=1.0).text("loop fraction"))
+                .drag_stopped();
+        }
+        regen |= ui
+            .add(
+                egui::Slider::new(&mut config.smoothing_iterations, 0..=10)
+                    .text("smoothing iterations"),
+            )
+            .drag_stopped();
+        regen |= ui
+            .add(
+                egui::Slider::new(&mut config.min_wall_thickness, 0..=8).text("min wall thickness"),
+            )
+            .drag_stopped();
+        regen |= ui
+            .add(egui::Slider::new(&mut config.min_floor_region, 0..=256).text("min floor region"))
+            .drag_stopped();
+        ui.horizontal_wrapped(|ui| {
+            ui.label("prefabs");
+            for name in available_prefabs() {
+                let mut enabled = config.prefabs.contains(&name);
+                if ui.checkbox(&mut enabled, &name).changed() {
+                    config.prefabs.retain(|prefab| *prefab != name);
+                    if enabled {
+                        config.prefabs.push(name);
+                    }
+                    regen = true;
+                }
+            }
+        });
+        regen |= ui
+            .add(egui::Slider::new(&mut config.prefab_chance, 0.0..=1.0).text("prefab chance"))
+            .drag_stopped();
+        ui.horizontal(|ui| {
+            ui.label("biomes from");
+            regen |= ui
+                .radio_value(&mut config.biome_source, BiomeSource::Noise, "noise")
+                .changed();
+            regen |= ui
+                .radio_value(&mut config.biome_source, BiomeSource::Depth, "depth")
+                .changed();
+        });
+        if config.biome_source == BiomeSource::Noise {
+            regen |= ui
+                .add(
+                    egui::Slider::new(&mut config.biome_scale, 0.002..=0.2)
+                        .logarithmic(true)
+                        .text("biome scale"),
+                )
+                .drag_stopped();
+        }
+        regen |= ui
+            .add(egui::Slider::new(&mut config.ore_chance, 0.0..=1.0).text("ore chance"))
+            .drag_stopped();
+        regen |= ui
+            .add(
+                egui::Slider::new(&mut config.soft_rock_threshold, -1.0..=1.0)
+                    .text("soft rock threshold"),
+            )
+            .drag_stopped();
+        regen |= ui
+            .add(egui::Slider::new(&mut config.water_depth, 0..=8).text("water depth"))
+            .drag_stopped();
+        regen |= ui
+            .add(egui::Slider::new(&mut config.chest_chance, 0.0..=1.0).text("chest chance"))
+            .drag_stopped();
+        regen |= ui
+            .add(egui::Slider::new(&mut config.torch_chance, 0.0..=0.5).text("torch chance"))
+            .drag_stopped();
+        ui.horizontal(|ui| {
+            ui.label("unreachable pockets");
+            regen |= ui
+                .radio_value(&mut config.pocket_mode, PocketMode::Tunnel, "tunnel")
+                .changed();
+            regen |= ui
+                .radio_value(&mut config.pocket_mode, PocketMode::Remove, "remove")
+                .changed();
+        });
+        regen |= ui
+            .add(egui::Slider::new(&mut config.exits, 0..=8).text("exits"))
+            .drag_stopped();
+        regen |= ui
+            .add(egui::Slider::new(&mut config.opening_width, 1.0..=16.0).text("opening width"))
+            .drag_stopped();
+        regen |= ui
+            .add(egui::Slider::new(&mut config.spawn_points, 0..=64).text("spawn points"))
+            .drag_stopped();
+        regen |= ui
+            .add(egui::Slider::new(&mut config.spawn_headroom, 0..=16).text("spawn headroom"))
+            .drag_stopped();
+        regen |= ui
+            .add(egui::Slider::new(&mut config.spawn_spacing, 0.0..=64.0).text("spawn spacing"))
+            .drag_stopped();
+
+        for stats in stats.iter() {
+            ui.separator();
+            ui.label(format!(
+                "{} nodes, {} tree edges, {} loop edges",
+                stats.nodes, stats.tree_edges, stats.loop_edges
+            ));
+            ui.label(format!(
+                "{} pockets ({} removed, {} tunneled), {} floor tiles",
+                stats.pockets, stats.pockets_removed, stats.pockets_tunneled, stats.floor_tiles
+            ));
+            ui.label(format!(
+                "{} dead ends, {} corridors, {} hubs, {} on cycles",
+                analysis.of_class(DegreeClass::DeadEnd).count(),
+                analysis.of_class(DegreeClass::Corridor).count(),
+                analysis.of_class(DegreeClass::Hub).count(),
+                analysis.nodes.iter().filter(|node| node.on_cycle).count(),
+            ));
+            if let Some(depth) = analysis
+                .deepest_dead_end()
+                .and_then(|node| analysis.get(node)?.depth)
+            {
+                ui.label(format!(
+                    "deepest dead end {depth} of {} tunnels down",
+                    analysis.max_depth().unwrap_or(0)
+                ));
+            }
+        }
+        ui.horizontal(|ui| {
+            if ui.button("save").clicked() {
+                saves.send(SaveCave);
+            }
+            egui::ComboBox::from_id_salt("load cave")
+                .selected_text("load")
+                .show_ui(ui, |ui| {
+                    for name in saved_caves() {
+                        if ui.selectable_label(false, &name).clicked() {
+                            loads.send(LoadCave(name));
+                        }
+                    }
+                });
+            for format in GraphFormat::ALL {
+                if ui
+                    .button(format!("export {}", format.extension()))
+                    .clicked()
+                {
+                    exports.send(ExportGraph(format));
+                }
+            }
+        });
+        let masks = available_masks();
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_salt("import mask")
+                .selected_text("import mask")
+                .show_ui(ui, |ui| {
+                    for name in masks.iter() {
+                        if ui.selectable_label(false, name).clicked() {
+                            imports.send(ImportMask(name.clone()));
+                        }
+                    }
+                });
+            egui::ComboBox::from_label("seed mask")
+                .selected_text(config.seed_mask.as_deref().unwrap_or("none"))
+                .show_ui(ui, |ui| {
+                    regen |= ui
+                        .selectable_value(&mut config.seed_mask, None, "none")
+                        .changed();
+                    for name in masks.iter() {
+                        regen |= ui
+                            .selectable_value(&mut config.seed_mask, Some(name.clone()), name)
+                            .changed();
+                    }
+                });
+        });
+        regen |= ui
+            .add(egui::Slider::new(&mut config.mask_threshold, 0..=255).text("mask threshold"))
+            .drag_stopped();
+        regen |= ui
+            .checkbox(&mut config.resize_masks, "resize masks to map")
+            .changed();
+
+        ui.separator();
+        ui.checkbox(&mut debug.draw_graph, "draw graph");
+        regen |= ui
+            .checkbox(&mut debug.step_through, "step through")
+            .changed();
+        for stepping in stepping.iter() {
+            ui.label(stepping.0.stage_name());
+            ui.horizontal(|ui| {
+                if ui.button("next step").clicked() {
+                    steps.send(Step::Step);
+                }
+                if ui.button("next stage").clicked() {
+                    steps.send(Step::Stage);
+                }
+            });
+        }
+        for task in tasks.iter() {
+            ui.add(
+                egui::ProgressBar::new(task.progress.fraction())
+                    .text(task.progress.stage())
+                    .animate(true),
+            );
+        }
+
+        if regen {
+            events.send(Regen);
+        }
+    });
+}
+
+#[derive(Event)]
+pub struct Regen;
+
+/// Advances a [`Stepping`] cave.
+#[derive(Event)]
+pub enum Step {
+    Step,
+    Stage,
+}
+
+/// Saves the current cave to [`SAVE_DIR`], named after its seed.
+#[derive(Event)]
+pub struct SaveCave;
+
+/// Loads the named cave from [`SAVE_DIR`] in place of the current one.
+#[derive(Event)]
+pub struct LoadCave(pub String);
+
+/// Replaces the current cave with the named mask from `assets/`[`masks::MASK_DIR`].
+#[derive(Event)]
+pub struct ImportMask(pub String);
+
+/// Exports the current cave's graph to [`SAVE_DIR`], named after its seed.
+#[derive(Event)]
+pub struct ExportGraph(pub GraphFormat);
+
+#[derive(Resource, Default)]
+pub struct CaveDebug {
+    /// Draw the nodes and edges of the cave graph with gizmos.
+    pub draw_graph: bool,
+    /// Generate caves a step at a time instead of on a task, advanced by [`Step`] events.
+    pub step_through: bool,
+}
+
+/// Generator settings, also loadable as a [`CavePreset`](presets::CavePreset) from
+/// `assets/cave_presets/*.preset.ron`. Fields missing from a preset keep their defaults, except the
+/// seed, which keeps its current value.
+#[derive(Resource, Reflect, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Seeds every random choice made during generation, so the same seed and config always
+    /// produce the same cave.
+    pub seed: u64,
+    pub placer: Placer,
+    pub min_area: f32,
+    /// How far from the middle a BSP split may land, as a fraction of the rectangle, 0 to always
+    /// split in half.
+    pub bsp_split_jitter: f32,
+    /// Range of the spacing between Poisson-disk samples, in tiles.
+    pub min_node_spacing: f32,
+    pub max_node_spacing: f32,
+    /// Size of a jittered grid cell, in tiles.
+    pub jitter_cell_size: f32,
+    /// How far a jittered grid room may stray from its cell's centre, as a fraction of the cell.
+    pub grid_jitter: f32,
+    pub connector: Connector,
+    pub grid_size: usize,
+    pub edge_neighbors: usize,
+    /// Number of spline segments a tunnel is made of.
+    pub tunnel_segments: usize,
+    /// Furthest a tunnel strays sideways from the straight line between its rooms, in tiles.
+    pub tunnel_meander: f32,
+    /// Frequency of the noise that pushes tunnels sideways.
+    pub tunnel_meander_scale: f32,
+    /// How much narrower a tunnel is in the middle than at its ends, from 0 to 1.
+    pub tunnel_taper: f32,
+    pub tunnel_thickness: f32,
+    pub node_radius_factor: f32,
+    pub node_color_factor: f32,
+    pub edge_color_factor: f32,
+    pub trunc_falloff_factor: f32,
+    /// Fraction of the connected edges left out of the spanning tree that are added back as loops.
+    pub loop_fraction: f32,
+    /// Loops added back with [`Connector::MstLoops`].
+    pub extra_loops: usize,
+    pub pocket_mode: PocketMode,
+    /// Iterations of the 4-5 cellular automaton run over the rasterized tiles.
+    pub smoothing_iterations: usize,
+    /// Walls thinner than this many tiles are removed, 0 to keep all walls. With an even value,
+    /// walls one tile thinner are kept too.
+    pub min_wall_thickness: u8,
+    /// Floor regions with fewer tiles than this are filled in, 0 to keep all regions.
+    pub min_floor_region: usize,
+    /// Tunnels out through the sides or bottom of the map, besides the entrance at the top.
+    pub exits: usize,
+    /// Width of the entrance and exit tunnels, in tiles.
+    pub opening_width: f32,
+    /// Most [`SpawnPoint`]s placed, at most one per chamber.
+    pub spawn_points: usize,
+    /// Open tiles needed above a spawn point's floor.
+    pub spawn_headroom: u32,
+    /// Closest two spawn points may be, in tiles.
+    pub spawn_spacing: f32,
+    /// A PNG in `assets/`[`masks::MASK_DIR`] whose floor is the only place rooms are put, stretched
+    /// over the map.
+    pub seed_mask: Option<String>,
+    /// Mask pixels at least this bright are floor.
+    pub mask_threshold: u8,
+    /// Stretch imported masks to the map, instead of resizing the map to the mask.
+    pub resize_masks: bool,
+    /// Prefabs in `assets/`[`prefabs::PREFAB_DIR`] that rooms may be replaced with.
+    pub prefabs: Vec<String>,
+    /// Chance of a room being replaced with one of [`Config::prefabs`].
+    pub prefab_chance: f32,
+    pub biome_source: BiomeSource,
+    /// Frequency of the noise biomes are picked from with [`BiomeSource::Noise`].
+    pub biome_scale: f32,
+    /// Chance of a crystal wall next to the floor being ore.
+    pub ore_chance: f32,
+    /// Walls where the soft rock noise is above this are soft rock, 1 for none.
+    pub soft_rock_threshold: f32,
+    /// Frequency of the soft rock noise.
+    pub soft_rock_scale: f32,
+    /// Most rows of water wet basins fill with.
+    pub water_depth: u32,
+    /// Chance of a dead end getting a chest.
+    pub chest_chance: f32,
+    /// Chance of a torch on each spot one could stand or hang.
+    pub torch_chance: f32,
+    /// Closest two animated tiles may be, in tiles.
+    pub animated_spacing: f32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            placer: Placer::Bsp,
+            min_area: 16.0,
+            bsp_split_jitter: 0.0,
+            min_node_spacing: 6.0,
+            max_node_spacing: 16.0,
+            jitter_cell_size: 12.0,
+            grid_jitter: 0.8,
+            connector: Connector::Delaunay,
+            grid_size: 64,
+            edge_neighbors: 3,
+            tunnel_segments: 10,
+            tunnel_meander: 6.0,
+            tunnel_meander_scale: 0.05,
+            tunnel_taper: 0.3,
+            tunnel_thickness: 0.1,
+            node_radius_factor: 0.1,
+            node_color_factor: 256.0,
+            edge_color_factor: 256.0,
+            trunc_falloff_factor: 0.05,
+            loop_fraction: 0.1,
+            extra_loops: 8,
+            pocket_mode: PocketMode::Tunnel,
+            smoothing_iterations: 2,
+            min_wall_thickness: 0,
+            min_floor_region: 16,
+            exits: 1,
+            opening_width: 4.0,
+            spawn_points: 8,
+            spawn_headroom: 4,
+            spawn_spacing: 16.0,
+            seed_mask: None,
+            mask_threshold: 128,
+            resize_masks: true,
+            prefabs: vec![],
+            prefab_chance: 0.1,
+            biome_source: BiomeSource::Noise,
+            biome_scale: 0.02,
+            ore_chance: 0.3,
+            soft_rock_threshold: 0.3,
+            soft_rock_scale: 0.08,
+            water_depth: 2,
+            chest_chance: 0.5,
+            torch_chance: 0.05,
+            animated_spacing: 12.0,
+        }
+    }
+}
+
+impl Config {
+    /// This config with the fields generation can't cope with brought back into range. The sliders
+    /// already keep them there, but presets and saves are edited by hand.
+    pub fn clamped(mut self) -> Self {
+        let fraction = |value: f32| value.clamp(0.0, 1.0);
+        self.min_area = self.min_area.max(1.0);
+        self.bsp_split_jitter = self.bsp_split_jitter.clamp(0.0, 0.9);
+        self.min_node_spacing = self.min_node_spacing.max(1.0);
+        self.max_node_spacing = self.max_node_spacing.max(self.min_node_spacing);
+        self.jitter_cell_size = self.jitter_cell_size.max(1.0);
+        self.grid_jitter = fraction(self.grid_jitter);
+        self.grid_size = self.grid_size.max(1);
+        self.tunnel_segments = self.tunnel_segments.max(1);
+        self.tunnel_taper = fraction(self.tunnel_taper);
+        self.trunc_falloff_factor = fraction(self.trunc_falloff_factor);
+        self.loop_fraction = fraction(self.loop_fraction);
+        self.opening_width = self.opening_width.max(1.0);
+        self.prefab_chance = fraction(self.prefab_chance);
+        self.ore_chance = fraction(self.ore_chance);
+        self.chest_chance = fraction(self.chest_chance);
+        self.torch_chance = fraction(self.torch_chance);
+        self
+    }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct CaveNode {
+    pub position: Vec2,
+    pub radius: f32,
+    /// Name of the [`Prefab`](prefabs::Prefab) stamped here instead of a round room.
+    #[serde(default)]
+    pub prefab: Option<String>,
+    #[serde(default)]
+    pub biome: Biome,
+}
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CaveEdge {
+    /// Width of the tunnel at either end, in tiles.
+    pub width: f32,
+}
+
+impl CaveEdge {
+    /// A tunnel [`Config::tunnel_thickness`] times as wide as the average size of its two rooms.
+    pub fn between(a: &CaveNode, b: &CaveNode, config: &Config) -> Self {
+        Self {
+            width: (a.radius + b.radius) * config.tunnel_thickness,
+        }
+    }
+}
+
+#[derive(Component, Default)]
+#[require(Transform, Generating, InheritedVisibility, CaveStats)]
+pub struct Caves {
+    pub size: Vec2,
+    /// The config the cave was generated with.
+    pub config: Config,
+    pub graph: UnGraph<CaveNode, CaveEdge>,
+    /// The rasterized tiles, 255 for floor and 0 for wall.
+    pub mask: GrayImage,
+    pub landmarks: Landmarks,
+    /// Row by row like [`Caves::mask`].
+    pub biomes: Vec<TileBiome>,
+    /// Row by row like [`Caves::mask`], empty for caves made from a mask alone.
+    pub tiles: Vec<TileType>,
+    pub animated: Vec<(UVec2, AnimatedKind)>,
+}
+
+impl Caves {
+    /// An empty cave filling the whole map, ready to be generated.
+    pub fn new(map: &MapConfig) -> Self {
+        Self {
+            size: Vec2::new(map.size.x as f32, map.size.y as f32),
+            ..default()
+        }
+    }
+
+    /// Records a tile changed after generation, such as one dug out, so the mask and tiles keep
+    /// matching the terrain. Tiles off the cave are ignored.
+    pub fn set_tile(&mut self, pos: UVec2, tile: TileType, solid: bool) {
+        let (width, height) = self.mask.dimensions();
+        if pos.x >= width || pos.y >= height {
+            return;
+        }
+        self.mask
+            .put_pixel(pos.x, pos.y, Luma([if solid { 0 } else { 255 }]));
+        if let Some(cave_tile) = self.tiles.get_mut((pos.y * width + pos.x) as usize) {
+            *cave_tile = tile;
+        }
+    }
+}
+
+/// Marks a cave whose generation hasn't finished yet. Any previous cave stays in place until then.
+#[derive(Component, Default)]
+pub struct Generating;
+
+/// Generation of a [`Generating`] cave, running on the [`AsyncComputeTaskPool`].
+#[derive(Component)]
+pub struct GenerateTask {
+    task: Task<GeneratedCave>,
+    pub progress: Progress,
+}
+
+/// Generation of a [`Generating`] cave that only moves on when sent a [`Step`].
+#[derive(Component)]
+pub struct Stepping(pub Generator);
+
+/// How far a generation task has got, shared between the task and the UI.
+#[derive(Clone, Default)]
+pub struct Progress {
+    current: Arc<AtomicUsize>,
+    stages: Arc<[&'static str]>,
+}
+
+impl Progress {
+    fn new(generator: &Generator) -> Self {
+        Self {
+            current: default(),
+            stages: generator.stage_names().into(),
+        }
+    }
+    fn set(&self, current: usize) {
+        self.current.store(current, Ordering::Relaxed);
+    }
+    pub fn fraction(&self) -> f32 {
+        if self.stages.is_empty() {
+            return 1.0;
+        }
+        self.current.load(Ordering::Relaxed) as f32 / self.stages.len() as f32
+    }
+    /// The stage currently running.
+    pub fn stage(&self) -> &'static str {
+        self.stages
+            .get(self.current.load(Ordering::Relaxed))
+            .copied()
+            .unwrap_or("done")
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn start_generation(
+    mut caves: Query<
+        (Entity, &mut Caves),
+        (With<Generating>, Without<GenerateTask>, Without<Stepping>),
+    >,
+    config: Res<Config>,
+    pipeline: Res<CavePipeline>,
+    debug: Res<CaveDebug>,
+    mut commands: Commands,
+) {
+    let pool = AsyncComputeTaskPool::get();
+    for (entity, mut system) in caves.iter_mut() {
+        debug!("start generating caves with seed {}", config.seed);
+        system.config = config.clone();
+        let generator = Generator::new(system.size, &config, &pipeline);
+        if debug.step_through {
+            commands.entity(entity).insert(Stepping(generator));
+            continue;
+        }
+        let progress = Progress::new(&generator);
+        let task = pool.spawn({
+            let progress = progress.clone();
+            async move { run(generator, &progress) }
+        });
+        commands
+            .entity(entity)
+            .insert(GenerateTask { task, progress });
+    }
+}
+
+fn step_generation(
+    mut stepping: Query<&mut Stepping>,
+    mut steps: EventReader<Step>,
+    mut events: EventWriter<SetTiles>,
+) {
+    for step in steps.read() {
+        for mut stepping in stepping.iter_mut() {
+            match step {
+                Step::Step => stepping.0.step(),
+                Step::Stage => stepping.0.finish_stage(),
+            }
+            // show the mask as it's drawn, the previous cave stays until then
+            if stepping.0.ctx.mask.pixels().any(|pixel| pixel[0] != 0) {
+                events.send(set_tiles(&stepping.0.ctx.mask, &stepping.0.ctx.tiles));
+            }
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn finish_generation(
+    mut caves: Query<
+        (
+            Entity,
+            &mut Caves,
+            &mut CaveStats,
+            Option<&mut GenerateTask>,
+            Option<&mut Stepping>,
+        ),
+        With<Generating>,
+    >,
+    old: Query<Entity, (With<Caves>, Without<Generating>)>,
+    mut analysis: ResMut<CaveAnalysis>,
+    mut events: EventWriter<SetTiles>,
+    mut biome_events: EventWriter<SetBiomes>,
+    mut animated_events: EventWriter<SetAnimated>,
+    mut commands: Commands,
+    terrain: TerrainSpace,
+) {
+    let space = terrain.get();
+    let to_world = |tile: UVec2| {
+        Transform::from_translation(space.tile_center(TilePos::new(tile.x, tile.y)).extend(0.0))
+    };
+
+    for (entity, mut system, mut stats, task, stepping) in caves.iter_mut() {
+        let cave = match (task, stepping) {
+            (Some(mut task), _) => block_on(future::poll_once(&mut task.task)),
+            (_, Some(mut stepping)) if stepping.0.is_done() => Some(stepping.0.take()),
+            _ => None,
+        };
+        let Some(cave) = cave else {
+            continue;
+        };
+        debug!("finished generating caves");
+        for old in old.iter() {
+            commands.entity(old).despawn_recursive();
+        }
+        system.graph = cave.graph;
+        let entrance = cave
+            .landmarks
+            .entrance
+            .map(|entrance| entrance.chamber)
+            .or_else(|| CaveAnalysis::topmost(&system.graph));
+        *analysis = CaveAnalysis::new(&system.graph, entrance);
+        *stats = cave.stats;
+        biome_events.send(set_biomes(&cave.biomes, &cave.mask));
+        events.send(set_tiles(&cave.mask, &cave.tiles));
+        animated_events.send(SetAnimated(
+            cave.animated
+                .iter()
+                .map(|(tile, kind)| (TilePos::new(tile.x, tile.y), *kind))
+                .collect(),
+        ));
+        system.mask = cave.mask;
+        commands.entity(entity).with_children(|parent| {
+            let landmarks = &cave.landmarks;
+            for entrance in landmarks.entrance.iter() {
+                parent.spawn((Entrance, to_world(entrance.tile)));
+            }
+            for exit in landmarks.exits.iter() {
+                parent.spawn((Exit, to_world(exit.tile)));
+            }
+            for tile in landmarks.spawn_points.iter() {
+                parent.spawn((SpawnPoint, to_world(*tile)));
+            }
+        });
+        system.landmarks = cave.landmarks;
+        system.biomes = cave.biomes;
+        system.tiles = cave.tiles;
+        system.animated = cave.animated;
+        commands
+            .entity(entity)
+            .remove::<(GenerateTask, Stepping, Generating)>();
+    }
+}
+
+pub struct GeneratedCave {
+    pub graph: UnGraph<CaveNode, CaveEdge>,
+    /// The rasterized tiles, 255 for floor and 0 for wall.
+    pub mask: GrayImage,
+    pub stats: CaveStats,
+    pub landmarks: Landmarks,
+    /// Row by row like the mask.
+    pub biomes: Vec<TileBiome>,
+    /// Row by row like the mask, walls and floors from the mask where empty.
+    pub tiles: Vec<TileType>,
+    pub animated: Vec<(UVec2, AnimatedKind)>,
+}
+
+/// Runs the default [`CavePipeline`] to completion.
+pub fn generate(size: Vec2, config: &Config) -> GeneratedCave {
+    let generator = Generator::new(size, config, &CavePipeline::default());
+    let progress = Progress::new(&generator);
+    run(generator, &progress)
+}
+
+fn run(mut generator: Generator, progress: &Progress) -> GeneratedCave {
+    while !generator.is_done() {
+        generator.step();
+        progress.set(generator.current());
+    }
+    generator.take()
+}
+
+fn reseed(mut config: ResMut<Config>, mut events: EventWriter<Regen>) {
+    config.seed = thread_rng().gen();
+    events.send(Regen);
+}
+
+fn regen(
+    generating: Query<Entity, (With<Caves>, With<Generating>)>,
+    mut commands: Commands,
+    map: Res<MapConfig>,
+) {
+    // abandon any generation still in progress, dropping its task cancels it
+    for entity in generating.iter() {
+        if let Some(e) = commands.get_entity(entity) {
+            e.try_despawn_recursive()
+        }
+    }
+
+    commands.spawn(Caves::new(&map));
+}
+
+fn save_cave(caves: Query<(&Caves, &CaveStats), Without<Generating>>) {
+    for (system, stats) in caves.iter() {
+        let path = Path::new(SAVE_DIR).join(format!("cave_{}.ron", system.config.seed));
+        match SavedCave::new(system, stats).save(&path) {
+            Ok(()) => info!("saved cave to {}", path.display()),
+            Err(err) => warn!("failed to save cave to {}: {err}", path.display()),
+        }
+    }
+}
+
+fn export_graph(mut exports: EventReader<ExportGraph>, caves: Query<&Caves, Without<Generating>>) {
+    for ExportGraph(format) in exports.read() {
+        for system in caves.iter() {
+            let path = Path::new(SAVE_DIR)
+                .join(format!("cave_{}", system.config.seed))
+                .with_extension(format.extension());
+            match format.write(&system.graph, &path) {
+                Ok(()) => info!("exported cave graph to {}", path.display()),
+                Err(err) => warn!("failed to export cave graph to {}: {err}", path.display()),
+            }
+        }
+    }
+}
+
+/// Replaces the current cave with a saved one.
+fn load_cave(
+    mut loads: EventReader<LoadCave>,
+    generating: Query<Entity, (With<Caves>, With<Generating>)>,
+    mut config: ResMut<Config>,
+    mut map: ResMut<MapConfig>,
+    mut commands: Commands,
+) {
+    for LoadCave(name) in loads.read() {
+        let path = Path::new(SAVE_DIR).join(name);
+        let saved = match SavedCave::load(&path) {
+            Ok(saved) => saved,
+            Err(err) => {
+                warn!("failed to load cave from {}: {err}", path.display());
+                continue;
+            }
+        };
+        debug!("load cave from {}", path.display());
+        for entity in generating.iter() {
+            if let Some(e) = commands.get_entity(entity) {
+                e.try_despawn_recursive()
+            }
+        }
+
+        let size = saved.size();
+        if map.size.x != size.x || map.size.y != size.y {
+            map.size = TilemapSize {
+                x: size.x,
+                y: size.y,
+            };
+        }
+        *config = saved.config.clone();
+        spawn_finished(&mut commands, size, &config, saved.into_cave());
+    }
+}
+
+/// Replaces the current cave with a hand-drawn one that has tiles but no graph.
+fn import_mask(
+    mut imports: EventReader<ImportMask>,
+    generating: Query<Entity, (With<Caves>, With<Generating>)>,
+    config: Res<Config>,
+    mut map: ResMut<MapConfig>,
+    mut commands: Commands,
+) {
+    for ImportMask(name) in imports.read() {
+        let size = config
+            .resize_masks
+            .then_some(UVec2::new(map.size.x, map.size.y));
+        let mask = match masks::load_mask(name, config.mask_threshold, size) {
+            Ok(mask) => mask,
+            Err(err) => {
+                warn!("failed to import mask {name}: {err}");
+                continue;
+            }
+        };
+        debug!("import mask {name}");
+        for entity in generating.iter() {
+            if let Some(e) = commands.get_entity(entity) {
+                e.try_despawn_recursive()
+            }
+        }
+
+        let size = UVec2::from(mask.dimensions());
+        if map.size.x != size.x || map.size.y != size.y {
+            map.size = TilemapSize {
+                x: size.x,
+                y: size.y,
+            };
+        }
+        let stats = CaveStats {
+            floor_tiles: mask.pixels().filter(|pixel| pixel[0] == 255).count(),
+            ..default()
+        };
+        let cave = GeneratedCave {
+            graph: default(),
+            mask,
+            stats,
+            landmarks: default(),
+            biomes: vec![],
+            tiles: vec![],
+            animated: vec![],
+        };
+        spawn_finished(&mut commands, size, &config, cave);
+    }
+}
+
+/// Spawns a cave that doesn't need generating. It's handed over as an already finished
+/// [`GenerateTask`] so it goes through the same swap, tiles and dmap as a generated one.
+fn spawn_finished(commands: &mut Commands, size: UVec2, config: &Config, cave: GeneratedCave) {
+    commands.spawn((
+        Caves {
+            size: size.as_vec2(),
+            config: config.clone(),
+            ..default()
+        },
+        GenerateTask {
+            task: AsyncComputeTaskPool::get().spawn(async move { cave }),
+            progress: Progress::default(),
+        },
+    ));
+}
+
+/// Every tile of `mask` with its biome, dry where `biomes` doesn't cover it.
+fn set_biomes(biomes: &[TileBiome], mask: &GrayImage) -> SetBiomes {
+    let (width, height) = mask.dimensions();
+    let set_biomes = (0..height)
+        .cartesian_product(0..width)
+        .map(|(y, x)| {
+            let biome = biomes
+                .get((y * width + x) as usize)
+                .copied()
+                .unwrap_or_default();
+            (TilePos { x, y }, biome)
+        })
+        .collect_vec();
+    SetBiomes(set_biomes)
+}
+
+/// Every tile of `img` with its kind from `tiles`, or a plain floor or wall if `tiles` doesn't
+/// cover it.
+fn set_tiles(img: &GrayImage, tiles: &[TileType]) -> SetTiles {
+    let set_tiles = img
+        .iter()
+        .enumerate()
+        .map(|(i, pixel)| {
+            let x = i as u32 % img.width();
+            let y = i as u32 / img.width();
+            let tile = tiles.get(i).copied().unwrap_or(if *pixel == 255 {
+                TileType::Floor
+            } else {
+                TileType::Wall
+            });
+            (TilePos { x, y }, tile)
+        })
+        .collect_vec();
+
+    SetTiles(set_tiles)
+}
+
+/// Draws the graph of the cave being stepped through, or of the current cave otherwise, on top of
+/// the tilemap. The current cave's chambers are coloured by [`DegreeClass`], and its entrance, exits
+/// and spawn points are marked.
+fn draw_graph(
+    mut gizmos: Gizmos,
+    caves: Query<&Caves, Without<Generating>>,
+    stepping: Query<&Stepping>,
+    analysis: Res<CaveAnalysis>,
+    config: Res<Config>,
+    terrain: TerrainSpace,
+) {
+    let space = terrain.get();
+    let grid = space.grid;
+    let to_world = |position: Vec2| space.to_world(position);
+
+    let graphs = if stepping.is_empty() {
+        caves.iter().map(|caves| &caves.graph).collect_vec()
+    } else {
+        stepping
+            .iter()
+            .map(|stepping| &stepping.0.ctx.graph)
+            .collect_vec()
+    };
+    for stepping in stepping.iter() {
+        stepping.0.draw(&mut gizmos, &to_world);
+    }
+    for graph in graphs {
+        for edge in graph.edge_references() {
+            let a = graph[edge.source()].position;
+            let b = graph[edge.target()].position;
+            gizmos.line_2d(to_world(a), to_world(b), ORANGE);
+        }
+        for (i, node) in graph.node_weights().enumerate() {
+            let degree = stepping
+                .is_empty()
+                .then(|| analysis.nodes.get(i).map(|node| node.degree))
+                .flatten();
+            let color = match degree {
+                Some(DegreeClass::DeadEnd) => RED,
+                Some(DegreeClass::Hub) => LIME,
+                _ => YELLOW,
+            };
+            gizmos.circle_2d(
+                to_world(node.position),
+                node.radius * config.node_radius_factor * grid.x,
+                color,
+            );
+        }
+    }
+    if !stepping.is_empty() {
+        return;
+    }
+    for caves in caves.iter() {
+        let landmarks = &caves.landmarks;
+        let tile_to_world = |tile: UVec2| space.tile_center(TilePos::new(tile.x, tile.y));
+        for opening in landmarks.entrance.iter().chain(landmarks.exits.iter()) {
+            gizmos.circle_2d(
+                tile_to_world(opening.tile),
+                caves.config.opening_width / 2.0 * grid.x,
+                WHITE,
+            );
+        }
+        for tile in landmarks.spawn_points.iter() {
+            gizmos.circle_2d(tile_to_world(*tile), grid.x, AQUA);
+        }
+    }
+}
+
+fn insert_dmap(
+    tile_storage: Single<Entity, (With<TileStorage>, Without<AnimatedLayer>)>,
+    mut commands: Commands,
+    caves: Query<Entity, (With<Caves>, Without<Generating>, Without<DMap>)>,
+    map: Res<MapConfig>,
+) {
+    for cave in caves.iter() {
+        debug!("insert dmap for caves");
+        let mut cave = commands.entity(cave);
+        let dmap = cave
+            .insert(DMap::new(
+                map.size.x as usize,
+                map.size.y as usize,
+                *tile_storage,
+            ))
+            .id();
+        commands.send_event(UpdateDMap(dmap));
+    }
+}
@@ -0,0 +1,268 @@
+use std::f32::consts::TAU;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{math::trunc_falloff, prelude::*};
+
+use super::{
+    generator::{CaveContext, CaveStage},
+    CaveNode,
+};
+
+/// How the rooms of a cave are placed, before they are connected.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Reflect, Serialize, Deserialize)]
+pub enum Placer {
+    #[default]
+    Bsp,
+    PoissonDisk,
+    JitteredGrid,
+}
+
+impl Placer {
+    pub const ALL: [Placer; 3] = [Placer::Bsp, Placer::PoissonDisk, Placer::JitteredGrid];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Placer::Bsp => "bsp",
+            Placer::PoissonDisk => "poisson disk",
+            Placer::JitteredGrid => "jittered grid",
+        }
+    }
+
+    /// A fresh stage placing rooms into `ctx` with this placer.
+    pub fn stage(self, ctx: &CaveContext) -> Box<dyn CaveStage> {
+        match self {
+            Placer::Bsp => Box::new(Bsp::new(ctx)),
+            Placer::PoissonDisk => Box::new(PoissonDisk::default()),
+            Placer::JitteredGrid => Box::new(JitteredGrid::default()),
+        }
+    }
+}
+
+/// Places nodes by recursively splitting the map, turning each rectangle into a room once it's
+/// small enough or by chance. Splits land within [`Config::bsp_split_jitter`](super::Config) of
+/// the middle. Rooms outside the seed mask are dropped.
+pub struct Bsp {
+    /// Rectangles still waiting to be split or turned into rooms.
+    stack: Vec<Rect>,
+}
+
+impl Bsp {
+    pub fn new(ctx: &CaveContext) -> Self {
+        Self {
+            stack: vec![Rect::new(0.0, 0.0, ctx.size.x, ctx.size.y)],
+        }
+    }
+}
+
+impl CaveStage for Bsp {
+    fn name(&self) -> &'static str {
+        "splitting rooms"
+    }
+
+    fn step(&mut self, ctx: &mut CaveContext) -> bool {
+        let Some(rect) = self.stack.pop() else {
+            return true;
+        };
+        let config = &ctx.config;
+        let area = rect.size().element_product();
+        let chance = area.remap(config.min_area, ctx.size.element_product(), 0.0, 1.0);
+        let chance = trunc_falloff(chance, 1.0) * config.trunc_falloff_factor;
+        // a rect the size of the map when that's the min area remaps to NaN, stop splitting it
+        let chance = if chance.is_nan() {
+            1.0
+        } else {
+            chance.clamp(0.0, 1.0)
+        };
+        if area < config.min_area || ctx.rng.gen_bool(chance as f64) {
+            if ctx.allows(rect.center()) {
+                ctx.graph.add_node(CaveNode {
+                    position: rect.center(),
+                    radius: rect.width().max(rect.height()),
+                    ..default()
+                });
+            }
+            return self.stack.is_empty();
+        }
+
+        let vertical = ctx.rng.gen_bool(0.5);
+        let jitter = config.bsp_split_jitter.clamp(0.0, 0.9) / 2.0;
+        let ratio = if jitter > 0.0 {
+            ctx.rng.gen_range(0.5 - jitter..=0.5 + jitter)
+        } else {
+            0.5
+        };
+        if vertical {
+            let mid = rect.min.x + rect.width() * ratio;
+            self.stack
+                .push(Rect::new(rect.min.x, rect.min.y, mid, rect.max.y));
+            self.stack
+                .push(Rect::new(mid, rect.min.y, rect.max.x, rect.max.y));
+        } else {
+            let mid = rect.min.y + rect.height() * ratio;
+            self.stack
+                .push(Rect::new(rect.min.x, rect.min.y, rect.max.x, mid));
+            self.stack
+                .push(Rect::new(rect.min.x, mid, rect.max.x, rect.max.y));
+        }
+        false
+    }
+
+    fn draw(&self, gizmos: &mut Gizmos, to_world: &dyn Fn(Vec2) -> Vec2) {
+        for rect in self.stack.iter() {
+            let min = to_world(rect.min);
+            let max = to_world(rect.max);
+            gizmos.rect_2d((min + max) / 2.0, max - min, GRAY);
+        }
+    }
+}
+
+/// Candidates tried around an active sample before it's retired.
+const POISSON_ATTEMPTS: usize = 30;
+
+/// Bridson's Poisson-disk sampling with a random spacing per sample, between
+/// [`Config::min_node_spacing`](super::Config) and `max_node_spacing`. No two rooms are closer than
+/// the larger of their spacings.
+#[derive(Default)]
+pub struct PoissonDisk {
+    /// Every sample so far, with its spacing.
+    samples: Vec<(Vec2, f32)>,
+    /// Samples that may still have room for neighbours around them.
+    active: Vec<usize>,
+    /// Indices into `samples`, bucketed by cells of the largest spacing.
+    buckets: Vec<Vec<usize>>,
+    columns: usize,
+    cell: f32,
+}
+
+impl PoissonDisk {
+    fn bucket(&self, position: Vec2) -> (usize, usize) {
+        (
+            (position.x / self.cell) as usize,
+            (position.y / self.cell) as usize,
+        )
+    }
+
+    fn fits(&self, position: Vec2, spacing: f32) -> bool {
+        let (x, y) = self.bucket(position);
+        let rows = self.buckets.len() / self.columns;
+        (x.saturating_sub(1)..=(x + 1).min(self.columns - 1))
+            .cartesian_product(y.saturating_sub(1)..=(y + 1).min(rows - 1))
+            .flat_map(|(x, y)| self.buckets[y * self.columns + x].iter())
+            .all(|i| {
+                let (other, other_spacing) = self.samples[*i];
+                position.distance(other) >= spacing.max(other_spacing)
+            })
+    }
+
+    fn add(&mut self, ctx: &mut CaveContext, position: Vec2, spacing: f32) {
+        let (x, y) = self.bucket(position);
+        self.buckets[y * self.columns + x].push(self.samples.len());
+        self.active.push(self.samples.len());
+        self.samples.push((position, spacing));
+        // samples outside the seed mask still spread, but don't become rooms
+        if ctx.allows(position) {
+            ctx.graph.add_node(CaveNode {
+                position,
+                // reach the neighbours a sample was spaced from when connecting
+                radius: spacing * 2.0,
+                ..default()
+            });
+        }
+    }
+}
+
+impl CaveStage for PoissonDisk {
+    fn name(&self) -> &'static str {
+        "sampling rooms"
+    }
+
+    fn step(&mut self, ctx: &mut CaveContext) -> bool {
+        let min = ctx.config.min_node_spacing.max(1.0);
+        let max = ctx.config.max_node_spacing.max(min);
+        if self.samples.is_empty() {
+            self.cell = max;
+            self.columns = (ctx.size.x / max).ceil().max(1.0) as usize;
+            let rows = (ctx.size.y / max).ceil().max(1.0) as usize;
+            self.buckets = vec![vec![]; self.columns * rows];
+            let position = Vec2::new(
+                ctx.rng.gen_range(0.0..ctx.size.x),
+                ctx.rng.gen_range(0.0..ctx.size.y),
+            );
+            let spacing = ctx.rng.gen_range(min..=max);
+            self.add(ctx, position, spacing);
+            return false;
+        }
+
+        if self.active.is_empty() {
+            return true;
+        }
+        let slot = ctx.rng.gen_range(0..self.active.len());
+        let (origin, origin_spacing) = self.samples[self.active[slot]];
+        let found = (0..POISSON_ATTEMPTS).find_map(|_| {
+            let angle = ctx.rng.gen_range(0.0..TAU);
+            let distance = ctx.rng.gen_range(origin_spacing..=origin_spacing * 2.0);
+            let position = origin + Vec2::from_angle(angle) * distance;
+            let spacing = ctx.rng.gen_range(min..=max);
+            let inside = position.cmpge(Vec2::ZERO).all() && position.cmplt(ctx.size).all();
+            (inside && self.fits(position, spacing)).then_some((position, spacing))
+        });
+        match found {
+            Some((position, spacing)) => self.add(ctx, position, spacing),
+            None => {
+                self.active.swap_remove(slot);
+            }
+        }
+        self.active.is_empty()
+    }
+
+    fn draw(&self, gizmos: &mut Gizmos, to_world: &dyn Fn(Vec2) -> Vec2) {
+        for i in self.active.iter() {
+            let (position, spacing) = self.samples[*i];
+            let world = to_world(position);
+            let radius = to_world(position + Vec2::X * spacing).x - world.x;
+            gizmos.circle_2d(world, radius, GRAY);
+        }
+    }
+}
+
+/// One room per [`Config::jitter_cell_size`](super::Config) cell of a grid over the map, pushed
+/// away from the cell's centre by up to `grid_jitter` of the cell.
+#[derive(Default)]
+pub struct JitteredGrid {
+    next: usize,
+}
+
+impl CaveStage for JitteredGrid {
+    fn name(&self) -> &'static str {
+        "jittering rooms"
+    }
+
+    fn step(&mut self, ctx: &mut CaveContext) -> bool {
+        let cell = ctx.config.jitter_cell_size.max(1.0);
+        let columns = (ctx.size.x / cell).floor().max(1.0) as usize;
+        let rows = (ctx.size.y / cell).floor().max(1.0) as usize;
+        // spread any leftover space evenly between the cells
+        let cell_size = ctx.size / Vec2::new(columns as f32, rows as f32);
+        if self.next >= columns * rows {
+            return true;
+        }
+
+        let (x, y) = (self.next % columns, self.next / columns);
+        let centre = (Vec2::new(x as f32, y as f32) + 0.5) * cell_size;
+        let jitter = ctx.config.grid_jitter.clamp(0.0, 1.0);
+        let offset = Vec2::new(ctx.rng.gen_range(-0.5..=0.5), ctx.rng.gen_range(-0.5..=0.5))
+            * jitter
+            * cell_size;
+        if ctx.allows(centre + offset) {
+            ctx.graph.add_node(CaveNode {
+                position: centre + offset,
+                // reach the diagonal neighbours even when jittered apart
+                radius: cell_size.max_element() * 2.0,
+                ..default()
+            });
+        }
+        self.next += 1;
+        self.next >= columns * rows
+    }
+}
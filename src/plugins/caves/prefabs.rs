@@ -0,0 +1,144 @@
+use std::{error::Error, fs, path::Path};
+
+use image::{GrayImage, Luma};
+use serde::Deserialize;
+
+use crate::prelude::*;
+
+/// Prefabs live in `assets/` under this folder, one RON file per prefab.
+pub const PREFAB_DIR: &str = "cave_prefabs";
+
+/// The prefabs in `assets/`[`PREFAB_DIR`], sorted by name.
+pub fn available_prefabs() -> Vec<String> {
+    let Ok(entries) = fs::read_dir(Path::new("assets").join(PREFAB_DIR)) else {
+        return vec![];
+    };
+    let mut names: Vec<String> = entries
+        .flatten()
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| name.ends_with(".ron"))
+        .collect();
+    names.sort();
+    names
+}
+
+/// A prefab as written by hand, one string per row from the top down. `#` is wall, `.` floor, `+`
+/// a door tunnels connect to, and a space is left as the cave made it.
+#[derive(Deserialize)]
+struct PrefabFile {
+    tiles: Vec<String>,
+}
+
+/// An authored chamber stamped into the cave in place of a generated room.
+#[derive(Clone, Debug)]
+pub struct Prefab {
+    /// File name in [`PREFAB_DIR`], which is how [`CaveNode::prefab`](super::CaveNode) refers to
+    /// it.
+    pub name: String,
+    pub size: UVec2,
+    /// Rows from `y = 0`, `Some(true)` for floor, `Some(false)` for wall and `None` to leave alone.
+    cells: Vec<Option<bool>>,
+    /// Door tiles, relative to the prefab's bottom left corner.
+    pub doors: Vec<UVec2>,
+}
+
+impl Prefab {
+    pub fn load(name: &str) -> Result<Self, Box<dyn Error>> {
+        let path = Path::new("assets").join(PREFAB_DIR).join(name);
+        let file: PrefabFile = ron::from_str(&fs::read_to_string(path)?)?;
+        let width = file
+            .tiles
+            .iter()
+            .map(|row| row.chars().count())
+            .max()
+            .unwrap_or(0);
+        let size = UVec2::new(width as u32, file.tiles.len() as u32);
+        let mut cells = vec![None; width * file.tiles.len()];
+        let mut doors = vec![];
+        // flipped, so the prefab is the right way up in the cave
+        for (y, row) in file.tiles.iter().rev().enumerate() {
+            for (x, glyph) in row.chars().enumerate() {
+                cells[y * width + x] = match glyph {
+                    '#' => Some(false),
+                    '.' => Some(true),
+                    '+' => {
+                        doors.push(UVec2::new(x as u32, y as u32));
+                        Some(true)
+                    }
+                    _ => None,
+                };
+            }
+        }
+        Ok(Self {
+            name: name.to_string(),
+            size,
+            cells,
+            doors,
+        })
+    }
+
+    /// Loads every named prefab, skipping any that fail to load.
+    pub fn load_all(names: &[String]) -> Vec<Self> {
+        names
+            .iter()
+            .filter_map(|name| {
+                Self::load(name)
+                    .inspect_err(|err| warn!("failed to load prefab {name}: {err}"))
+                    .ok()
+            })
+            .collect()
+    }
+
+    /// Bottom left tile of the prefab when centred on `centre`.
+    fn origin(&self, centre: Vec2) -> IVec2 {
+        centre.as_ivec2() - (self.size / 2).as_ivec2()
+    }
+
+    /// The tiles the prefab covers when centred on `centre`.
+    pub fn bounds(&self, centre: Vec2) -> IRect {
+        let origin = self.origin(centre);
+        IRect::from_corners(origin, origin + self.size.as_ivec2())
+    }
+
+    /// Draws the prefab into `mask` centred on `centre`, clipped to the mask.
+    pub fn blit(&self, mask: &mut GrayImage, centre: Vec2) {
+        let origin = self.origin(centre);
+        for (i, cell) in self.cells.iter().enumerate() {
+            let Some(floor) = cell else {
+                continue;
+            };
+            let tile =
+                origin + IVec2::new(i as i32 % self.size.x as i32, i as i32 / self.size.x as i32);
+            if tile.cmplt(IVec2::ZERO).any()
+                || tile.x >= mask.width() as i32
+                || tile.y >= mask.height() as i32
+            {
+                continue;
+            }
+            mask.put_pixel(
+                tile.x as u32,
+                tile.y as u32,
+                Luma([if *floor { 255 } else { 0 }]),
+            );
+        }
+    }
+
+    /// The door nearest `target` when centred on `centre`, or the centre if there are no doors.
+    pub fn door_towards(&self, centre: Vec2, target: Vec2) -> Vec2 {
+        let origin = self.origin(centre).as_vec2();
+        self.doors
+            .iter()
+            .map(|door| origin + door.as_vec2() + 0.5)
+            .min_by(|a, b| {
+                a.distance_squared(target)
+                    .total_cmp(&b.distance_squared(target))
+            })
+            .unwrap_or(centre)
+    }
+}
+
+/// The prefab a room refers to, if it's one of `prefabs`.
+pub fn find<'a>(prefabs: &'a [Prefab], name: Option<&str>) -> Option<&'a Prefab> {
+    let name = name?;
+    prefabs.iter().find(|prefab| prefab.name == name)
+}
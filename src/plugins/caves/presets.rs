@@ -0,0 +1,295 @@
+use std::{error::Error, fs, path::Path};
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, LoadContext, LoadedFolder},
+    ecs::system::SystemParam,
+};
+use serde::{Deserialize, Deserializer};
+
+use crate::prelude::*;
+
+use super::{Config, Regen};
+
+/// Presets live in `assets/` under this folder, one RON file per preset.
+const PRESET_DIR: &str = "cave_presets";
+/// Presets have their own extension so other RON assets aren't loaded as presets.
+const PRESET_EXTENSION: &str = "preset.ron";
+
+pub(super) fn presets_plugin(app: &mut App) {
+    app.init_asset::<CavePreset>();
+    app.register_asset_loader(PresetLoader);
+    app.init_resource::<CavePresets>();
+    app.add_systems(Update, (collect_presets, reload_preset));
+}
+
+/// A [`Config`] loaded from a preset file.
+#[derive(Asset, TypePath, Clone, PartialEq)]
+pub struct CavePreset {
+    pub config: Config,
+    /// The seed, only when the file sets one. Picking a preset without one keeps the current seed.
+    pub seed: Option<u64>,
+}
+
+impl CavePreset {
+    pub fn apply(&self, config: &mut Config) {
+        let seed = self.seed.unwrap_or(config.seed);
+        *config = self.config.clone();
+        config.seed = seed;
+    }
+}
+
+/// Just the seed of a preset file, to tell whether it's set.
+#[derive(Deserialize)]
+struct PresetSeed {
+    #[serde(default, deserialize_with = "some")]
+    seed: Option<u64>,
+}
+
+fn some<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+    u64::deserialize(deserializer).map(Some)
+}
+
+#[derive(Default)]
+struct PresetLoader;
+
+impl AssetLoader for PresetLoader {
+    type Asset = CavePreset;
+    type Settings = ();
+    type Error = Box<dyn Error + Send + Sync>;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<CavePreset, Self::Error> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes).await?;
+        let PresetSeed { seed } = ron::de::from_bytes(&bytes)?;
+        let config: Config = ron::de::from_bytes(&bytes)?;
+        let clamped = config.clone().clamped();
+        if clamped != config {
+            warn!(
+                "preset {} has settings out of range, clamped them",
+                load_context.path().display()
+            );
+        }
+        Ok(CavePreset {
+            config: clamped,
+            seed,
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &[PRESET_EXTENSION]
+    }
+}
+
+#[derive(Resource)]
+pub struct CavePresets {
+    folder: Handle<LoadedFolder>,
+    presets: Vec<Handle<CavePreset>>,
+    /// The preset [`Config`] was last set from, kept in sync with its file.
+    selected: Option<Handle<CavePreset>>,
+    /// Name to save the current config under.
+    name: String,
+    /// Whether saved presets keep the current seed, or leave it to whoever picks them.
+    save_seed: bool,
+}
+
+impl FromWorld for CavePresets {
+    fn from_world(world: &mut World) -> Self {
+        Self {
+            folder: world.resource::<AssetServer>().load_folder(PRESET_DIR),
+            presets: vec![],
+            selected: None,
+            name: "preset".to_string(),
+            save_seed: false,
+        }
+    }
+}
+
+/// Everything the Caves window needs to pick and save presets.
+#[derive(SystemParam)]
+pub struct PresetUi<'w> {
+    presets: ResMut<'w, CavePresets>,
+    configs: Res<'w, Assets<CavePreset>>,
+    asset_server: Res<'w, AssetServer>,
+}
+
+impl PresetUi<'_> {
+    /// Draws the preset dropdown and save button, returning whether a preset was applied to
+    /// `config`.
+    pub fn show(&mut self, ui: &mut egui::Ui, config: &mut Config) -> bool {
+        let mut changed = false;
+        let name_of = |handle: &Handle<CavePreset>| {
+            handle
+                .path()
+                .and_then(|path| path.path().file_name())
+                .map_or_else(
+                    || "?".to_string(),
+                    |name| {
+                        let name = name.to_string_lossy();
+                        let stem = name.strip_suffix(&format!(".{PRESET_EXTENSION}"));
+                        stem.unwrap_or(&name).to_string()
+                    },
+                )
+        };
+        let selected = self
+            .presets
+            .selected
+            .as_ref()
+            .map_or_else(|| "none".to_string(), name_of);
+        let mut choice = None;
+        egui::ComboBox::from_label("preset")
+            .selected_text(selected)
+            .show_ui(ui, |ui| {
+                for handle in self.presets.presets.iter() {
+                    if ui.selectable_label(false, name_of(handle)).clicked() {
+                        choice = Some(handle.clone());
+                    }
+                }
+            });
+        if let Some(preset) = choice.and_then(|handle| {
+            let preset = self.configs.get(&handle)?.clone();
+            self.presets.selected = Some(handle);
+            Some(preset)
+        }) {
+            preset.apply(config);
+            changed = true;
+        }
+
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.presets.name);
+            ui.checkbox(&mut self.presets.save_seed, "with seed");
+            let valid = valid_preset_name(&self.presets.name);
+            let save = ui
+                .add_enabled(valid, egui::Button::new("save current as preset"))
+                .on_disabled_hover_text("name the preset without path separators or \"..\"");
+            if save.clicked() {
+                let path = format!("{PRESET_DIR}/{}.{PRESET_EXTENSION}", self.presets.name);
+                let saved = save_preset(
+                    config,
+                    self.presets.save_seed,
+                    &Path::new("assets").join(&path),
+                );
+                match saved {
+                    Ok(()) => {
+                        let handle = self.asset_server.load(path);
+                        if !self.presets.presets.contains(&handle) {
+                            self.presets.presets.push(handle.clone());
+                        }
+                        self.presets.selected = Some(handle);
+                    }
+                    Err(err) => warn!("failed to save preset {path}: {err}"),
+                }
+            }
+        });
+
+        changed
+    }
+}
+
+/// Whether a preset can be saved as `name`, which has to stay a file in [`PRESET_DIR`].
+fn valid_preset_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains(['/', '\\']) && !name.contains("..")
+}
+
+fn save_preset(config: &Config, with_seed: bool, path: &Path) -> Result<(), Box<dyn Error>> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, preset_ron(config, with_seed)?)?;
+    Ok(())
+}
+
+/// `config` as a preset file, leaving out the seed unless `with_seed`.
+fn preset_ron(config: &Config, with_seed: bool) -> Result<String, ron::Error> {
+    let ron = ron::ser::to_string_pretty(config, ron::ser::PrettyConfig::default())?;
+    if with_seed {
+        return Ok(ron);
+    }
+    // the seed is a number on its own line, one indent into the config
+    Ok(ron
+        .lines()
+        .filter(|line| !line.starts_with("    seed:"))
+        .join("\n"))
+}
+
+fn collect_presets(
+    mut events: EventReader<AssetEvent<LoadedFolder>>,
+    mut presets: ResMut<CavePresets>,
+    folders: Res<Assets<LoadedFolder>>,
+) {
+    for event in events.read() {
+        let AssetEvent::LoadedWithDependencies { id } = event else {
+            continue;
+        };
+        if *id != presets.folder.id() {
+            continue;
+        }
+        let Some(folder) = folders.get(*id) else {
+            continue;
+        };
+        for handle in folder.handles.iter() {
+            let Ok(handle) = handle.clone().try_typed::<CavePreset>() else {
+                continue;
+            };
+            if !presets.presets.contains(&handle) {
+                presets.presets.push(handle);
+            }
+        }
+        presets
+            .presets
+            .sort_by_key(|handle| handle.path().map(|path| path.to_string()));
+    }
+}
+
+/// Applies edits to the selected preset's file as soon as they are saved.
+fn reload_preset(
+    mut events: EventReader<AssetEvent<CavePreset>>,
+    presets: Res<CavePresets>,
+    configs: Res<Assets<CavePreset>>,
+    mut config: ResMut<Config>,
+    mut regen: EventWriter<Regen>,
+) {
+    for event in events.read() {
+        let AssetEvent::Modified { id } = event else {
+            continue;
+        };
+        if presets.selected.as_ref().map(|handle| handle.id()) != Some(*id) {
+            continue;
+        }
+        let Some(preset) = configs.get(*id) else {
+            continue;
+        };
+        let mut reloaded = config.clone();
+        preset.apply(&mut reloaded);
+        if reloaded != *config {
+            debug!("reload cave preset");
+            *config = reloaded;
+            regen.send(Regen);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_save_the_seed_only_when_asked() {
+        let config = Config {
+            seed: 1234,
+            ..default()
+        };
+        for with_seed in [false, true] {
+            let ron = preset_ron(&config, with_seed).unwrap();
+            let PresetSeed { seed } = ron::de::from_str(&ron).unwrap();
+            assert_eq!(seed, with_seed.then_some(1234));
+            let mut loaded: Config = ron::de::from_str(&ron).unwrap();
+            loaded.seed = config.seed;
+            assert!(loaded == config);
+        }
+    }
+}
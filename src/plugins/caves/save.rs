@@ -0,0 +1,175 @@
+use std::{error::Error, fs, path::Path};
+
+use image::{GrayImage, Luma};
+use petgraph::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    plugins::terrain::{animated::AnimatedKind, Biome, TileBiome, TileType},
+    prelude::*,
+};
+
+use super::{
+    connectivity::CaveStats, landmarks::Landmarks, CaveEdge, CaveNode, Caves, Config, GeneratedCave,
+};
+
+/// Where caves are saved to and loaded from.
+pub const SAVE_DIR: &str = "saves";
+
+/// A generated cave as written to disk, enough to restore it without regenerating.
+#[derive(Serialize, Deserialize)]
+pub struct SavedCave {
+    /// The config the cave was generated with, including its seed.
+    pub config: Config,
+    pub graph: UnGraph<CaveNode, CaveEdge>,
+    pub stats: CaveStats,
+    /// One string per row of tiles starting at `y = 0`, one glyph per [`TileType`], see
+    /// [`tile_glyph`].
+    pub tiles: Vec<String>,
+    /// Missing from caves saved before entrances were placed.
+    #[serde(default)]
+    pub landmarks: Landmarks,
+    /// Rows like `tiles`, the first letter of each tile's biome, in upper case if decorated.
+    #[serde(default)]
+    pub biomes: Vec<String>,
+    #[serde(default)]
+    pub animated: Vec<(UVec2, AnimatedKind)>,
+}
+
+impl SavedCave {
+    pub fn new(caves: &Caves, stats: &CaveStats) -> Self {
+        let width = caves.mask.width() as usize;
+        let tiles = caves
+            .mask
+            .rows()
+            .enumerate()
+            .map(|(y, row)| {
+                row.enumerate()
+                    .map(|(x, pixel)| match caves.tiles.get(y * width + x) {
+                        Some(tile) => tile_glyph(*tile),
+                        None if pixel[0] == 255 => '.',
+                        None => '#',
+                    })
+                    .collect()
+            })
+            .collect();
+        Self {
+            config: caves.config.clone(),
+            graph: caves.graph.clone(),
+            stats: stats.clone(),
+            tiles,
+            landmarks: caves.landmarks.clone(),
+            biomes: caves
+                .biomes
+                .chunks(caves.mask.width().max(1) as usize)
+                .map(|row| row.iter().map(|tile| biome_glyph(*tile)).collect())
+                .collect(),
+            animated: caves.animated.clone(),
+        }
+    }
+
+    pub fn size(&self) -> UVec2 {
+        let width = self.tiles.first().map_or(0, |row| row.chars().count());
+        UVec2::new(width as u32, self.tiles.len() as u32)
+    }
+
+    pub fn into_cave(self) -> GeneratedCave {
+        let size = self.size();
+        let mut mask = GrayImage::new(size.x, size.y);
+        let mut tiles = vec![];
+        for (y, row) in self.tiles.iter().enumerate() {
+            for (x, glyph) in row.chars().take(size.x as usize).enumerate() {
+                let (tile, floor) = glyph_tile(glyph);
+                if floor {
+                    mask.put_pixel(x as u32, y as u32, Luma([255]));
+                }
+                tiles.push(tile);
+            }
+        }
+        GeneratedCave {
+            graph: self.graph,
+            mask,
+            stats: self.stats,
+            landmarks: self.landmarks,
+            biomes: self
+                .biomes
+                .iter()
+                .flat_map(|row| row.chars().take(size.x as usize).map(glyph_biome))
+                .collect(),
+            tiles,
+            animated: self.animated,
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let ron = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        fs::write(path, ron)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Ok(ron::from_str(&fs::read_to_string(path)?)?)
+    }
+}
+
+/// `#` wall, `.` floor, `~` water, `$` ore, `%` soft rock, `,` guano and `@` bedrock.
+fn tile_glyph(tile: TileType) -> char {
+    match tile {
+        TileType::Wall => '#',
+        TileType::Floor => '.',
+        TileType::Water => '~',
+        TileType::Ore => '$',
+        TileType::SoftRock => '%',
+        TileType::Guano => ',',
+        TileType::Bedrock => '@',
+    }
+}
+
+/// The tile a glyph stands for, and whether it was carved out as floor of the cave's mask.
+fn glyph_tile(glyph: char) -> (TileType, bool) {
+    match glyph {
+        '.' => (TileType::Floor, true),
+        '~' => (TileType::Water, true),
+        ',' => (TileType::Guano, true),
+        '$' => (TileType::Ore, false),
+        '%' => (TileType::SoftRock, false),
+        '@' => (TileType::Bedrock, false),
+        _ => (TileType::Wall, false),
+    }
+}
+
+fn biome_glyph(tile: TileBiome) -> char {
+    let glyph = tile.biome.name().chars().next().unwrap();
+    if tile.decorated {
+        glyph.to_ascii_uppercase()
+    } else {
+        glyph
+    }
+}
+
+fn glyph_biome(glyph: char) -> TileBiome {
+    TileBiome {
+        biome: Biome::ALL
+            .into_iter()
+            .find(|biome| biome.name().starts_with(glyph.to_ascii_lowercase()))
+            .unwrap_or_default(),
+        decorated: glyph.is_ascii_uppercase(),
+    }
+}
+
+/// The caves in [`SAVE_DIR`], sorted by name.
+pub fn saved_caves() -> Vec<String> {
+    let Ok(entries) = fs::read_dir(SAVE_DIR) else {
+        return vec![];
+    };
+    let mut names: Vec<String> = entries
+        .flatten()
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| name.ends_with(".ron"))
+        .collect();
+    names.sort();
+    names
+}
@@ -0,0 +1,134 @@
+use bevy::utils::tracing::instrument;
+use image::{GrayImage, Luma};
+use imageproc::{
+    distance_transform::Norm,
+    morphology::close_mut,
+    region_labelling::{connected_components, Connectivity},
+};
+
+use crate::{
+    plugins::terrain::{Biome, TileBiome},
+    prelude::*,
+};
+
+use super::Config;
+
+/// Runs the optional post-rasterization passes over a mask (255 for floor, 0 for wall): the 4-5
+/// cellular automaton, then the minimum wall thickness and minimum floor region filters. Each tile
+/// gets [`Biome::extra_smoothing`](crate::plugins::terrain::Biome) more or fewer automaton
+/// iterations, going by `biomes` if it covers the mask.
+#[instrument(skip_all)]
+pub fn smooth(img: &mut GrayImage, config: &Config, biomes: &[TileBiome]) {
+    let width = img.width();
+    let iterations = |x: u32, y: u32| {
+        let extra = biomes
+            .get((y * width + x) as usize)
+            .map_or(0, |tile| tile.biome.extra_smoothing());
+        config.smoothing_iterations.saturating_add_signed(extra)
+    };
+    let most = Biome::ALL
+        .iter()
+        .map(|biome| {
+            config
+                .smoothing_iterations
+                .saturating_add_signed(biome.extra_smoothing())
+        })
+        .max()
+        .unwrap_or(0);
+    for i in 0..most {
+        *img = automaton_step(img, |x, y| iterations(x, y) > i);
+    }
+    if config.min_wall_thickness > 1 {
+        // closing the floor removes any wall that the floor can grow through from both sides, so
+        // walls up to twice the radius thick
+        close_mut(img, Norm::LInf, (config.min_wall_thickness - 1) / 2);
+    }
+    if config.min_floor_region > 0 {
+        remove_small_regions(img, config.min_floor_region);
+    }
+}
+
+/// One step of the 4-5 rule: a tile becomes wall with 5 or more wall neighbours, floor with 3 or
+/// fewer, and is left alone with exactly 4. Tiles outside the map count as wall. Only tiles `update`
+/// returns true for are changed.
+fn automaton_step(img: &GrayImage, update: impl Fn(u32, u32) -> bool) -> GrayImage {
+    let (width, height) = img.dimensions();
+    let is_wall = |x: i32, y: i32| {
+        x < 0
+            || y < 0
+            || x >= width as i32
+            || y >= height as i32
+            || img.get_pixel(x as u32, y as u32)[0] == 0
+    };
+    GrayImage::from_fn(width, height, |x, y| {
+        if !update(x, y) {
+            return *img.get_pixel(x, y);
+        }
+        let (x, y) = (x as i32, y as i32);
+        let walls = (-1..=1)
+            .cartesian_product(-1..=1)
+            .filter(|offset| *offset != (0, 0))
+            .filter(|(dx, dy)| is_wall(x + dx, y + dy))
+            .count();
+        match walls {
+            0..4 => Luma([255]),
+            4 => *img.get_pixel(x as u32, y as u32),
+            _ => Luma([0]),
+        }
+    })
+}
+
+/// Fills floor regions smaller than `min_area` tiles with wall.
+fn remove_small_regions(img: &mut GrayImage, min_area: usize) {
+    let labels = connected_components(img, Connectivity::Four, Luma([0u8]));
+    let region_count = labels.pixels().map(|label| label[0]).max().unwrap_or(0) as usize;
+    let mut areas = vec![0usize; region_count + 1];
+    for label in labels.pixels() {
+        areas[label[0] as usize] += 1;
+    }
+    for (x, y, label) in labels.enumerate_pixels() {
+        if label[0] != 0 && areas[label[0] as usize] < min_area {
+            img.put_pixel(x, y, Luma([0]));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Floor with a wall `thickness` tiles thick down the middle.
+    fn wall(thickness: u32) -> GrayImage {
+        GrayImage::from_fn(thickness + 10, 5, |x, _| {
+            Luma([if (5..5 + thickness).contains(&x) {
+                0
+            } else {
+                255
+            }])
+        })
+    }
+
+    #[test]
+    fn walls_at_min_thickness_are_kept() {
+        for min_wall_thickness in 2..=8 {
+            let config = Config {
+                smoothing_iterations: 0,
+                min_wall_thickness,
+                min_floor_region: 0,
+                ..default()
+            };
+            let thickness = min_wall_thickness as u32;
+            let mut img = wall(thickness);
+            smooth(&mut img, &config, &[]);
+            assert_eq!(img, wall(thickness), "min thickness {min_wall_thickness}");
+            if min_wall_thickness % 2 == 1 {
+                let mut img = wall(thickness - 1);
+                smooth(&mut img, &config, &[]);
+                assert!(
+                    img.pixels().all(|pixel| pixel[0] == 255),
+                    "min thickness {min_wall_thickness}"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,242 @@
+use std::f32::consts::PI;
+
+use bevy::math::cubic_splines::*;
+use image::{GrayImage, Luma};
+use noise::{NoiseFn, Perlin};
+use petgraph::prelude::*;
+
+use crate::prelude::*;
+
+use super::{
+    connectivity,
+    generator::{CaveContext, CaveStage},
+    prefabs::{self, Prefab},
+    smoothing, CaveEdge, CaveNode, Config,
+};
+
+/// Replaces rooms with a random one of [`Config::prefabs`], each with [`Config::prefab_chance`].
+/// Only prefabs that fit within the room's radius, inside the map and clear of the prefabs already
+/// picked are considered.
+#[derive(Default)]
+pub struct PrefabPicker {
+    /// Tiles covered by the prefabs picked so far.
+    placed: Vec<IRect>,
+    next: usize,
+}
+
+impl CaveStage for PrefabPicker {
+    fn name(&self) -> &'static str {
+        "picking prefabs"
+    }
+
+    fn step(&mut self, ctx: &mut CaveContext) -> bool {
+        if ctx.prefabs.is_empty() || self.next >= ctx.graph.node_count() {
+            return true;
+        }
+        let chance = ctx.config.prefab_chance.clamp(0.0, 1.0) as f64;
+        if ctx.rng.gen_bool(chance) {
+            let node = &ctx.graph[NodeIndex::new(self.next)];
+            let map = IRect::from_corners(IVec2::ZERO, ctx.size.as_ivec2());
+            let fitting = ctx
+                .prefabs
+                .iter()
+                .filter(|prefab| prefab.size.max_element() as f32 <= node.radius)
+                .map(|prefab| (prefab, prefab.bounds(node.position)))
+                .filter(|(_, bounds)| map.union(*bounds) == map)
+                .filter(|(_, bounds)| {
+                    self.placed
+                        .iter()
+                        .all(|placed| placed.intersect(*bounds).is_empty())
+                })
+                .collect_vec();
+            if !fitting.is_empty() {
+                let (prefab, bounds) = fitting[ctx.rng.gen_range(0..fitting.len())];
+                self.placed.push(bounds);
+                ctx.graph[NodeIndex::new(self.next)].prefab = Some(prefab.name.clone());
+            }
+        }
+        self.next += 1;
+        self.next >= ctx.graph.node_count()
+    }
+}
+
+/// See [`connectivity::span`].
+pub struct SpanningTree;
+
+impl CaveStage for SpanningTree {
+    fn name(&self) -> &'static str {
+        "spanning graph"
+    }
+
+    fn step(&mut self, ctx: &mut CaveContext) -> bool {
+        connectivity::span(&mut ctx.graph, &ctx.config, &mut ctx.rng, &mut ctx.stats);
+        true
+    }
+}
+
+/// Draws every room into the mask as a circle [`Config::node_radius_factor`] times its radius, or
+/// as its prefab.
+#[derive(Default)]
+pub struct RoomRasterizer {
+    next: usize,
+}
+
+impl CaveStage for RoomRasterizer {
+    fn name(&self) -> &'static str {
+        "rasterizing rooms"
+    }
+
+    fn step(&mut self, ctx: &mut CaveContext) -> bool {
+        let Some(node) = ctx.graph.node_weight(NodeIndex::new(self.next)) else {
+            return true;
+        };
+        match prefabs::find(&ctx.prefabs, node.prefab.as_deref()) {
+            Some(prefab) => prefab.blit(&mut ctx.mask, node.position),
+            None => imageproc::drawing::draw_filled_circle_mut(
+                &mut ctx.mask,
+                (node.position.x as i32, node.position.y as i32),
+                (node.radius.ceil() * ctx.config.node_radius_factor) as i32,
+                Luma([255]),
+            ),
+        }
+        self.next += 1;
+        self.next >= ctx.graph.node_count()
+    }
+}
+
+/// Carves every edge into the mask with [`tunnel_between`].
+#[derive(Default)]
+pub struct TunnelCarver {
+    noise: Option<Perlin>,
+    next: usize,
+}
+
+impl CaveStage for TunnelCarver {
+    fn name(&self) -> &'static str {
+        "carving tunnels"
+    }
+
+    fn step(&mut self, ctx: &mut CaveContext) -> bool {
+        let noise = self.noise.get_or_insert_with(|| Perlin::new(ctx.rng.gen()));
+        if self.next >= ctx.graph.edge_count() {
+            return true;
+        }
+        tunnel_between(
+            &ctx.graph,
+            EdgeIndex::new(self.next),
+            &mut ctx.mask,
+            &ctx.config,
+            &ctx.prefabs,
+            noise,
+        );
+        self.next += 1;
+        self.next >= ctx.graph.edge_count()
+    }
+}
+
+/// See [`smoothing::smooth`].
+pub struct Smoothing;
+
+impl CaveStage for Smoothing {
+    fn name(&self) -> &'static str {
+        "smoothing"
+    }
+
+    fn step(&mut self, ctx: &mut CaveContext) -> bool {
+        smoothing::smooth(&mut ctx.mask, &ctx.config, &ctx.biomes);
+        true
+    }
+}
+
+/// Stamps the prefabs again after tunnels and smoothing have worn at them, so they stay as
+/// authored. Their doors stay open onto the tunnels that reach them.
+pub struct PrefabStamper;
+
+impl CaveStage for PrefabStamper {
+    fn name(&self) -> &'static str {
+        "stamping prefabs"
+    }
+
+    fn step(&mut self, ctx: &mut CaveContext) -> bool {
+        for node in ctx.graph.node_weights() {
+            if let Some(prefab) = prefabs::find(&ctx.prefabs, node.prefab.as_deref()) {
+                prefab.blit(&mut ctx.mask, node.position);
+            }
+        }
+        true
+    }
+}
+
+/// See [`connectivity::connect_regions`].
+pub struct RegionConnector;
+
+impl CaveStage for RegionConnector {
+    fn name(&self) -> &'static str {
+        "connecting regions"
+    }
+
+    fn step(&mut self, ctx: &mut CaveContext) -> bool {
+        connectivity::connect_regions(&mut ctx.mask, &ctx.config, &mut ctx.stats);
+        true
+    }
+}
+
+/// Carves a tunnel along a Catmull-Rom spline between the two rooms of `edge`. The control points
+/// are pushed sideways by `noise`, but pinned at both ends so the tunnel always finishes inside the
+/// target room, or at the door of a prefab nearest the other room. The tunnel is widened or
+/// narrowed by the [`Biome::tunnel_thickness`](crate::plugins::terrain::Biome) of both rooms.
+pub fn tunnel_between(
+    graph: &UnGraph<CaveNode, CaveEdge>,
+    edge: EdgeIndex,
+    map: &mut GrayImage,
+    config: &Config,
+    prefabs: &[Prefab],
+    noise: &Perlin,
+) {
+    let Some((a, b)) = graph.edge_endpoints(edge) else {
+        return;
+    };
+    let end = |node: &CaveNode, other: &CaveNode| {
+        prefabs::find(prefabs, node.prefab.as_deref()).map_or(node.position, |prefab| {
+            prefab.door_towards(node.position, other.position)
+        })
+    };
+    let thickness = (graph[a].biome.tunnel_thickness() + graph[b].biome.tunnel_thickness()) / 2.0;
+    tunnel(
+        end(&graph[a], &graph[b]),
+        end(&graph[b], &graph[a]),
+        graph[edge].width * thickness,
+        map,
+        config,
+        noise,
+    );
+}
+
+/// Carves a tunnel `width` tiles wide at its ends from `a` to `b`, see [`tunnel_between`].
+pub fn tunnel(a: Vec2, b: Vec2, width: f32, map: &mut GrayImage, config: &Config, noise: &Perlin) {
+    let normal = (b - a).perp().normalize_or_zero();
+    let segments = config.tunnel_segments.max(1);
+    let control_points = (0..=segments)
+        .map(|i| {
+            let t = i as f32 / segments as f32;
+            let point = a.lerp(b, t);
+            let offset = noise.get((point * config.tunnel_meander_scale).as_dvec2().to_array());
+            point + normal * offset as f32 * config.tunnel_meander * (PI * t).sin()
+        })
+        .collect_vec();
+    let Ok(curve) = CubicCardinalSpline::new_catmull_rom(control_points).to_curve() else {
+        return;
+    };
+
+    let subdivisions = ((a.distance(b) + config.tunnel_meander * 2.0) * 2.0).ceil() as usize;
+    for (i, position) in curve.iter_positions(subdivisions.max(1)).enumerate() {
+        let t = i as f32 / subdivisions.max(1) as f32;
+        let radius = width / 2.0 * (1.0 - config.tunnel_taper * (PI * t).sin());
+        imageproc::drawing::draw_filled_circle_mut(
+            map,
+            position.as_ivec2().into(),
+            radius.round().max(1.0) as i32,
+            Luma([255]),
+        );
+    }
+}
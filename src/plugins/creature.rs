@@ -2,7 +2,7 @@ use bevy::sprite::Material2d;
 
 use crate::prelude::*;
 
-use super::{pathfinding::DMap, physics::AddForces, terrain::MapConfig};
+use super::{pathfinding::DMap, physics::AddForces, terrain::coords::TerrainSpace};
 
 pub fn creature_plugin(app: &mut App) {
     app.add_systems(Startup, setup);
@@ -118,10 +118,11 @@ fn pathfind(
     mut bats: Query<(Entity, &Transform, &mut ExternalForce), With<Bat>>,
     mut commands: Commands,
     dmap: Single<&DMap>,
-    config: Res<MapConfig>,
+    terrain: TerrainSpace,
 ) {
+    let space = terrain.get();
     for (bat, trans, mut ext) in bats.iter_mut() {
-        let Some(coord) = config.world_to_tile(trans.translation.truncate()) else {
+        let Some(coord) = space.world_to_tile(trans.translation.truncate()) else {
             warn!("bat out of bounds");
             continue;
         };
@@ -147,7 +148,7 @@ fn pathfind(
         else {
             continue;
         };
-        let min = config.tile_to_world(TilePos::new(min.x as u32, min.y as u32));
+        let min = space.tile_center(TilePos::new(min.x as u32, min.y as u32));
         let dir = (min - trans.translation.truncate()).normalize();
         let force = Vec2::new(dir.x, dir.y)
             * 2000.0
@@ -3,12 +3,17 @@ use ndarray::{Array2, Axis};
 
 use crate::prelude::*;
 
-use super::terrain::{MapConfig, TileType};
+use super::terrain::{
+    animated::AnimatedLayer, coords::TileSpace, MapConfig, TileRegistry, TileType, UpdateTiles,
+};
 
 pub fn pathfinding_plugin(app: &mut App) {
     app.add_systems(
         Update,
-        (update_dmap, debug_render).run_if(on_event::<UpdateDMap>),
+        // after the tiles edited along with the update are set
+        (update_dmap, debug_render)
+            .run_if(on_event::<UpdateDMap>)
+            .after(UpdateTiles),
     );
     app.add_event::<UpdateDMap>();
 }
@@ -16,6 +21,8 @@ pub fn pathfinding_plugin(app: &mut App) {
 #[derive(Component)]
 pub struct DMap {
     values: Array2<Option<u32>>,
+    /// Cost of stepping onto each tile, from its [`TileProperties::path_cost`](super::terrain::TileProperties).
+    costs: Array2<u32>,
     tile_storage: Entity,
 }
 #[derive(Component)]
@@ -25,6 +32,7 @@ impl DMap {
     pub fn new(width: usize, height: usize, tile_storage: Entity) -> Self {
         Self {
             values: Array2::from_elem((width, height), None),
+            costs: Array2::from_elem((width, height), 1),
             tile_storage,
         }
     }
@@ -41,6 +49,7 @@ impl DMap {
         let mut dirty = true;
         let max_iter = 50;
         let mut n = 0;
+        let (width, height) = self.values.dim();
         while dirty && n < max_iter {
             dirty = false;
             // cell_view gives interior mutability, and it seems this algo calls for mutating as we go
@@ -52,17 +61,18 @@ impl DMap {
                         continue;
                     }
                     let left = (x.saturating_sub(1), y);
-                    let right = ((x + 1).clamp(0, 255), y);
+                    let right = ((x + 1).min(width - 1), y);
                     let up = (x, y.saturating_sub(1));
-                    let down = (x, (y + 1).clamp(0, 255));
-                    let min = [(x, y), left, right, up, down]
+                    let down = (x, (y + 1).min(height - 1));
+                    let min = [left, right, up, down]
                         .iter()
                         .filter_map(|idx| cells.get(*idx))
                         .filter_map(|c| c.get())
                         .min();
                     if let Some(min) = min {
-                        if min < cell.get().unwrap_or_default() {
-                            cell.set(Some(min.saturating_add(1)));
+                        let value = min.saturating_add(self.costs[(x, y)]);
+                        if value < cell.get().unwrap_or_default() {
+                            cell.set(Some(value));
                             dirty = true;
                         }
                     }
@@ -87,6 +97,7 @@ fn update_dmap(
     goals: Query<(), With<Goal>>,
     tile_storages: Query<&TileStorage>,
     tiles: Query<(&TilePos, &TileType, &TileColor)>,
+    registry: Res<TileRegistry>,
 ) {
     if goals.is_empty() {
         return;
@@ -106,10 +117,13 @@ fn update_dmap(
         for tile in tile_storage.iter() {
             let tile = tile.unwrap();
             let (pos, tile_type, _color) = tiles.get(tile).unwrap();
+            let idx = (pos.x as usize, pos.y as usize);
+            let cost = registry.get(*tile_type).path_cost;
+            dmap.costs[idx] = cost.unwrap_or(u32::MAX);
             if goals.contains(tile) {
-                dmap.values[(pos.x as usize, pos.y as usize)] = Some(0);
-            } else if let TileType::Floor = tile_type {
-                dmap.values[(pos.x as usize, pos.y as usize)] = Some(u32::MAX);
+                dmap.values[idx] = Some(0);
+            } else if cost.is_some() {
+                dmap.values[idx] = Some(u32::MAX);
             }
         }
 
@@ -119,7 +133,7 @@ fn update_dmap(
 
 fn debug_render(
     dmap: Single<&DMap>,
-    tile_storage: Single<&TileStorage>,
+    tilemap: Single<(&TileStorage, &Transform), Without<AnimatedLayer>>,
     mut tiles: Query<(&TilePos, &TileType, &mut TileColor)>,
     mut commands: Commands,
     tile_labels: Query<Entity, With<TileLabel>>,
@@ -129,6 +143,8 @@ fn debug_render(
         .iter()
         .for_each(|label| commands.entity(label).despawn());
 
+    let (tile_storage, tilemap_transform) = *tilemap;
+    let space = TileSpace::new(tilemap_transform, &config);
     let palette = ColorCurve::new([RED, PINK, SKY_BLUE, LIGHT_BLUE]).unwrap();
     for tile in tile_storage.iter() {
         let tile = tile.unwrap();
@@ -148,7 +164,7 @@ fn debug_render(
                     TileLabel(tile),
                     Text2d(val.to_string()),
                     TextFont::from_font_size(8.0),
-                    Transform::from_translation(config.tile_to_world(*pos).extend(1.0)),
+                    Transform::from_translation(space.tile_center(*pos).extend(1.0)),
                 ));
             }
         } else {
@@ -1,6 +1,11 @@
 use std::f32::consts::TAU;
 
-use crate::prelude::*;
+use crate::{
+    plugins::terrain::{
+        animated::AnimatedLayer, coords::TileSpace, MapConfig, TileBiome, TileRegistry, TileType,
+    },
+    prelude::*,
+};
 
 #[derive(Component)]
 #[require(Transform)]
@@ -17,6 +22,9 @@ pub struct CastData {
     pub point: Vec2,
     pub normal: Vec2,
     pub dir: Vec2,
+    /// Fraction of the sound left after this bounce, going by the kinds and biomes of the walls hit
+    /// so far.
+    pub energy: f32,
 }
 
 #[derive(Component)]
@@ -26,8 +34,27 @@ pub struct RayDebug(pub Entity);
 pub(super) fn cast_rays(
     casters: Query<(Entity, &Transform, &Raycaster)>,
     rapier_context: Single<&RapierContext>,
+    tilemap: Single<(&TileStorage, &Transform), Without<AnimatedLayer>>,
+    tiles: Query<(&TileType, &TileBiome)>,
+    registry: Res<TileRegistry>,
+    map: Res<MapConfig>,
     mut commands: Commands,
 ) {
+    let (tile_storage, tilemap_transform) = *tilemap;
+    let space = TileSpace::new(tilemap_transform, &map);
+    // the wall tile just behind where a ray hit
+    let absorption = |intersection: &RayIntersection| {
+        let behind = intersection.point - intersection.normal * space.grid / 2.0;
+        space
+            .world_to_tile(behind)
+            .and_then(|pos| tile_storage.checked_get(&pos))
+            .and_then(|tile| tiles.get(tile).ok())
+            .map_or(0.0, |(tile, biome)| {
+                let kept =
+                    (1.0 - registry.get(*tile).absorption) * (1.0 - biome.biome.absorption());
+                1.0 - kept
+            })
+    };
     for (entity, trans, caster) in casters.iter() {
         let rays = radial_cast(trans.translation.truncate(), 10, &rapier_context);
         let bounced = rays.map(|(_entity, intersection, dir)| {
@@ -46,11 +73,16 @@ pub(super) fn cast_rays(
         commands.entity(entity).insert(Raycasts(
             bounced
                 .map(|rays| {
-                    rays.map(|(intersection, dir, origin)| CastData {
-                        dir,
-                        origin,
-                        point: intersection.point,
-                        normal: intersection.normal,
+                    let mut energy = 1.0;
+                    rays.map(|(intersection, dir, origin)| {
+                        energy *= 1.0 - absorption(&intersection);
+                        CastData {
+                            dir,
+                            origin,
+                            point: intersection.point,
+                            normal: intersection.normal,
+                            energy,
+                        }
                     })
                     .collect_vec()
                 })
@@ -72,7 +104,8 @@ fn radial_cast(
             dir,
             1000.0,
             true,
-            QueryFilter::default(),
+            // sound carries through water
+            QueryFilter::default().exclude_sensors(),
         )?;
         Some((entity, intersection, dir))
     })
@@ -90,7 +123,8 @@ fn bounce_ray(
             dir,
             1000.0,
             false,
-            QueryFilter::default(),
+            // sound carries through water
+            QueryFilter::default().exclude_sensors(),
         )
         .map(|(_, intersection)| (intersection, dir))
 }
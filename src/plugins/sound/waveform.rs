@@ -45,7 +45,7 @@ impl Waveform {
             .into_iter()
             .map(|ray| {
                 let seg = ray.point - ray.origin;
-                let amplitude = seg.y;
+                let amplitude = seg.y * ray.energy;
                 let out = Vec2::new(t, amplitude);
                 t += seg.length();
                 out
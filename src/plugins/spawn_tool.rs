@@ -1,6 +1,6 @@
 use std::time::Duration;
 
-use bevy::window::PrimaryWindow;
+use bevy::{ecs::system::SystemParam, window::PrimaryWindow};
 
 use crate::{
     plugins::{creature::Bat, pathfinding::Goal},
@@ -8,15 +8,20 @@ use crate::{
 };
 
 use super::{
-    caves::Regen,
+    caves::landmarks::SpawnPoint,
+    caves::{Caves, Generating, Regen},
     pathfinding::{DMap, UpdateDMap},
-    terrain::MapConfig,
+    terrain::{
+        animated::AnimatedLayer, coords::TileSpace, MapConfig, SetTiles, TileRegistry, TileType,
+    },
 };
 
 pub fn spawn_tool_plugin(app: &mut App) {
     app.init_state::<Tool>();
+    app.init_resource::<AutoPopulate>();
+    app.init_resource::<DigRadius>();
     app.add_systems(Startup, spawn_player);
-    app.add_systems(Update, (spawn_at, mark_goal, ui));
+    app.add_systems(Update, (spawn_at, mark_goal, dig_at, explode, populate, ui));
     app.add_systems(FixedUpdate, send_update_dmap);
     app.add_plugins(InputManagerPlugin::<Action>::default());
 }
@@ -76,6 +81,14 @@ fn spawn_at(
                     Velocity::linear(vel.reflect(Vec2::Y) * 100.0),
                 ));
             }
+            Tool::Bomb => {
+                commands.spawn((
+                    Ball,
+                    Explosive::default(),
+                    Transform::from_translation(pos),
+                    Velocity::linear(vel.reflect(Vec2::Y) * 100.0),
+                ));
+            }
             _ => {}
         };
         timer.set_duration(Duration::from_millis(100));
@@ -83,6 +96,35 @@ fn spawn_at(
     }
 }
 
+/// Spawn a creature of the current [`Tool`] at every [`SpawnPoint`] of each new cave.
+#[derive(Resource, Default)]
+pub struct AutoPopulate(pub bool);
+
+fn populate(
+    auto: Res<AutoPopulate>,
+    tool: Res<State<Tool>>,
+    points: Query<&Transform, Added<SpawnPoint>>,
+    mut commands: Commands,
+) {
+    if !auto.0 {
+        return;
+    }
+    // spawn points are children of a cave at the origin, so their transform is already in world
+    // space before it's propagated
+    for transform in points.iter() {
+        let transform = Transform::from_translation(transform.translation);
+        match **tool {
+            Tool::Ball => {
+                commands.spawn((Ball, transform));
+            }
+            Tool::Bat => {
+                commands.spawn((Bat, transform));
+            }
+            _ => {}
+        }
+    }
+}
+
 #[allow(clippy::too_many_arguments)]
 fn mark_goal(
     tool: Res<State<Tool>>,
@@ -91,7 +133,7 @@ fn mark_goal(
     mut cursor: Local<Vec2>,
     mut commands: Commands,
     camera: Single<(&Camera, &GlobalTransform)>,
-    tile_storage: Single<&TileStorage>,
+    tilemap: Single<(&TileStorage, &Transform), Without<AnimatedLayer>>,
     config: Res<MapConfig>,
 ) {
     if let Some(pos) = events.read().last().map(|e| e.position) {
@@ -102,7 +144,9 @@ fn mark_goal(
         let pos = cursor_to_world(*cursor, camera.0, camera.1);
         debug!("mark goal at {:?}", pos);
 
-        if let Some(tile_pos) = config.world_to_tile(pos.truncate()) {
+        let (tile_storage, tilemap_transform) = *tilemap;
+        let space = TileSpace::new(tilemap_transform, &config);
+        if let Some(tile_pos) = space.world_to_tile(pos.truncate()) {
             let tile = tile_storage.get(&tile_pos).unwrap();
             commands
                 .entity(tile)
@@ -112,6 +156,159 @@ fn mark_goal(
     }
 }
 
+/// Radius in tiles dug out or filled in by [`Tool::Pickaxe`] and [`Tool::Fill`].
+#[derive(Resource)]
+pub struct DigRadius(pub u32);
+
+impl Default for DigRadius {
+    fn default() -> Self {
+        Self(1)
+    }
+}
+
+/// Digs out breakable walls, or fills in open tiles with wall, around the cursor while the button
+/// is held.
+#[allow(clippy::too_many_arguments)]
+fn dig_at(
+    tool: Res<State<Tool>>,
+    action_state: Single<&ActionState<Action>, With<Player>>,
+    mut events: EventReader<CursorMoved>,
+    mut cursor: Local<Vec2>,
+    camera: Single<(&Camera, &GlobalTransform)>,
+    tilemap: Single<(&TileStorage, &Transform), Without<AnimatedLayer>>,
+    tiles: Query<&TileType>,
+    mut edits: TerrainEdits,
+    radius: Res<DigRadius>,
+    registry: Res<TileRegistry>,
+    config: Res<MapConfig>,
+) {
+    if let Some(pos) = events.read().last().map(|e| e.position) {
+        *cursor = pos;
+    }
+    let (into, digging) = match **tool {
+        Tool::Pickaxe => (TileType::Floor, true),
+        Tool::Fill => (TileType::Wall, false),
+        _ => return,
+    };
+    if !action_state.pressed(&Action::SpawnAt) {
+        return;
+    }
+    let (tile_storage, tilemap_transform) = *tilemap;
+    let space = TileSpace::new(tilemap_transform, &config);
+    let pos = cursor_to_world(*cursor, camera.0, camera.1);
+    let Some(center) = space.world_to_tile(pos.truncate()) else {
+        return;
+    };
+    let changed = tiles_within(center, radius.0 as f32, tile_storage)
+        .filter(|(_, entity)| {
+            tiles.get(*entity).is_ok_and(|tile| {
+                let properties = registry.get(*tile);
+                properties.breakable && properties.solid == digging
+            })
+        })
+        .map(|(pos, _)| (pos, into))
+        .collect_vec();
+    edits.apply(changed);
+}
+
+/// A ball that blows a hole in the terrain once its fuse runs out.
+#[derive(Component)]
+pub struct Explosive {
+    pub fuse: Timer,
+    /// Radius of the hole, in tiles.
+    pub radius: f32,
+}
+
+impl Default for Explosive {
+    fn default() -> Self {
+        Self {
+            fuse: Timer::from_seconds(2.0, TimerMode::Once),
+            radius: 4.0,
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn explode(
+    mut explosives: Query<(Entity, &Transform, &mut Explosive)>,
+    tilemap: Single<(&TileStorage, &Transform), Without<AnimatedLayer>>,
+    tiles: Query<&TileType>,
+    mut edits: TerrainEdits,
+    mut commands: Commands,
+    registry: Res<TileRegistry>,
+    config: Res<MapConfig>,
+    time: Res<Time>,
+) {
+    let (tile_storage, tilemap_transform) = *tilemap;
+    let space = TileSpace::new(tilemap_transform, &config);
+    for (entity, transform, mut explosive) in explosives.iter_mut() {
+        if !explosive.fuse.tick(time.delta()).finished() {
+            continue;
+        }
+        commands.entity(entity).despawn_recursive();
+        let Some(center) = space.world_to_tile(transform.translation.truncate()) else {
+            continue;
+        };
+        let blown = tiles_within(center, explosive.radius, tile_storage)
+            .filter(|(_, entity)| {
+                tiles.get(*entity).is_ok_and(|tile| {
+                    let properties = registry.get(*tile);
+                    properties.breakable && properties.solid
+                })
+            })
+            .map(|(pos, _)| (pos, TileType::Floor))
+            .collect_vec();
+        debug!("explosion broke {} tiles", blown.len());
+        edits.apply(blown);
+    }
+}
+
+/// Edits the terrain, keeping the cave's data and its [`DMap`] up to date with it.
+#[derive(SystemParam)]
+struct TerrainEdits<'w, 's> {
+    set_tiles: EventWriter<'w, SetTiles>,
+    update_dmap: EventWriter<'w, UpdateDMap>,
+    caves: Query<'w, 's, (Entity, &'static mut Caves, Has<DMap>), Without<Generating>>,
+    registry: Res<'w, TileRegistry>,
+}
+
+impl TerrainEdits<'_, '_> {
+    fn apply(&mut self, changed: Vec<(TilePos, TileType)>) {
+        if changed.is_empty() {
+            return;
+        }
+        for (entity, mut caves, has_dmap) in self.caves.iter_mut() {
+            for (pos, tile) in changed.iter() {
+                let solid = self.registry.get(*tile).solid;
+                caves.set_tile(UVec2::new(pos.x, pos.y), *tile, solid);
+            }
+            if has_dmap {
+                self.update_dmap.send(UpdateDMap(entity));
+            }
+        }
+        self.set_tiles.send(SetTiles(changed));
+    }
+}
+
+/// The tiles at most `radius` tiles from `center`, with their entities.
+fn tiles_within<'a>(
+    center: TilePos,
+    radius: f32,
+    tile_storage: &'a TileStorage,
+) -> impl Iterator<Item = (TilePos, Entity)> + 'a {
+    let reach = radius.ceil() as i32;
+    (-reach..=reach)
+        .cartesian_product(-reach..=reach)
+        .filter(move |(x, y)| ((x * x + y * y) as f32) <= radius * radius)
+        .filter_map(move |(x, y)| {
+            let pos = TilePos {
+                x: center.x.checked_add_signed(x)?,
+                y: center.y.checked_add_signed(y)?,
+            };
+            Some((pos, tile_storage.checked_get(&pos)?))
+        })
+}
+
 fn send_update_dmap(
     tool: Res<State<Tool>>,
     action_state: Single<&ActionState<Action>, With<Player>>,
@@ -148,18 +345,31 @@ pub enum Tool {
     Ball,
     Bat,
     Goal,
+    /// Digs out walls.
+    Pickaxe,
+    /// Fills in open tiles with wall.
+    Fill,
+    /// Throws balls that explode.
+    Bomb,
 }
 
 fn ui(
     mut contexts: EguiContexts,
     state: Res<State<Tool>>,
     mut next_state: ResMut<NextState<Tool>>,
+    mut auto: ResMut<AutoPopulate>,
+    mut radius: ResMut<DigRadius>,
 ) {
     egui::Window::new("Spawn Tool").show(contexts.ctx_mut(), |ui| {
         let mut state = **state;
         ui.radio_value(&mut state, Tool::Ball, "Ball");
         ui.radio_value(&mut state, Tool::Bat, "Bat");
         ui.radio_value(&mut state, Tool::Goal, "Goal");
+        ui.radio_value(&mut state, Tool::Pickaxe, "Pickaxe");
+        ui.radio_value(&mut state, Tool::Fill, "Fill");
+        ui.radio_value(&mut state, Tool::Bomb, "Bomb");
         next_state.set(state);
+        ui.add(egui::Slider::new(&mut radius.0, 0..=8).text("dig radius"));
+        ui.checkbox(&mut auto.0, "populate spawn points");
     });
 }
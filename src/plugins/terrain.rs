@@ -1,56 +1,161 @@
+use bevy::utils::{HashMap, HashSet};
 use rand::thread_rng;
+use serde::{Deserialize, Serialize};
 
 use crate::prelude::*;
 
+pub mod animated;
+pub mod autotile;
+pub mod colliders;
+pub mod coords;
+
+use animated::AnimatedLayer;
+use colliders::ColliderBuilder;
+use coords::TileSpace;
+
 pub fn terrain_plugin(app: &mut App) {
-    app.insert_resource(MapConfig {
-        floor_idx: 35,
-        wall_idx: 72,
-        size: TilemapSize { x: 256, y: 256 },
-        tile_size: TilemapTileSize { x: 12.0, y: 12.0 },
-        grid_size: TilemapGridSize { x: 12.0, y: 12.0 },
-    });
+    app.add_plugins(autotile::autotile_plugin);
+    app.add_plugins(animated::animated_plugin);
+    app.init_resource::<MapConfig>();
+    app.init_resource::<TileRegistry>();
     app.add_event::<SetTiles>();
+    app.add_event::<SetBiomes>();
     app.init_resource::<Tileset>();
     app.add_systems(Startup, setup);
-    app.add_systems(Update, (set_tile_textures, set_tilemap_collider));
+    app.add_systems(Update, ui);
+    // the tilemap is respawned, with its commands applied, before anything reads the tiles sent
+    // for it, so they aren't spent on the old tilemap
+    app.add_systems(
+        Update,
+        (
+            resize_tilemap.run_if(on_event::<SetTiles>),
+            set_tile_textures,
+            autotile::resolve_autotiles,
+            set_tilemap_collider,
+        )
+            .chain()
+            .in_set(UpdateTiles),
+    );
 }
 
+/// Systems applying [`SetTiles`] to the tilemap.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct UpdateTiles;
+
 pub const FLOOR: u32 = 35;
 pub const WALL: u32 = 72;
 
 #[derive(Resource)]
 pub struct MapConfig {
-    pub floor_idx: u32,
-    pub wall_idx: u32,
+    /// Textures of each [`Biome`], in the order of [`Biome::ALL`].
+    pub biome_tiles: [BiomeTiles; 4],
+    /// Frames of each [`AnimatedKind`](animated::AnimatedKind) in `tiles/EnvAnimated.png`, in the
+    /// order of [`AnimatedKind::ALL`](animated::AnimatedKind::ALL).
+    pub animations: [AnimatedTile; 3],
+    pub collider_builder: ColliderBuilder,
     pub size: TilemapSize,
     pub tile_size: TilemapTileSize,
     pub grid_size: TilemapGridSize,
 }
 
-impl MapConfig {
-    pub fn world_to_tile(&self, pos: Vec2) -> Option<TilePos> {
-        let pos = pos
-            + Vec2::new(
-                self.size.x as f32 * self.tile_size.x,
-                self.size.y as f32 * self.tile_size.y,
-            ) / 2.
-            + Vec2::new(self.tile_size.x, self.tile_size.y) / 2.;
-        TilePos::from_world_pos(&pos, &self.size, &self.grid_size, &TilemapType::Square)
+impl Default for MapConfig {
+    fn default() -> Self {
+        Self {
+            biome_tiles: [
+                BiomeTiles {
+                    floor_idx: 35,
+                    wall_idx: 72,
+                    decoration_idx: 241,
+                },
+                BiomeTiles {
+                    floor_idx: 40,
+                    wall_idx: 71,
+                    decoration_idx: 243,
+                },
+                BiomeTiles {
+                    floor_idx: 45,
+                    wall_idx: 73,
+                    decoration_idx: 244,
+                },
+                BiomeTiles {
+                    floor_idx: 30,
+                    wall_idx: 60,
+                    decoration_idx: 246,
+                },
+            ],
+            animations: [
+                AnimatedTile {
+                    start: 0,
+                    end: 4,
+                    speed: 1.0,
+                },
+                AnimatedTile {
+                    start: 4,
+                    end: 8,
+                    speed: 0.8,
+                },
+                AnimatedTile {
+                    start: 8,
+                    end: 12,
+                    speed: 0.25,
+                },
+            ],
+            collider_builder: ColliderBuilder::default(),
+            size: TilemapSize { x: 256, y: 256 },
+            tile_size: TilemapTileSize { x: 12.0, y: 12.0 },
+            grid_size: TilemapGridSize { x: 12.0, y: 12.0 },
+        }
     }
-    pub fn tile_to_world(&self, pos: TilePos) -> Vec2 {
-        let pos = pos.center_in_world(&self.grid_size, &TilemapType::Square);
-        pos - Vec2::new(
-            self.size.x as f32 * self.tile_size.x,
-            self.size.y as f32 * self.tile_size.y,
-        ) / 2.
-            - Vec2::new(self.tile_size.x, self.tile_size.y) / 2.
+}
+
+impl MapConfig {
+    /// The texture of a tile of type `tile` in `biome`. Only walls and floors change with their
+    /// biome, other kinds always use their [`TileProperties::texture_idx`].
+    pub fn texture(&self, tile: TileType, biome: TileBiome, registry: &TileRegistry) -> u32 {
+        let tiles = &self.biome_tiles[biome.biome as usize];
+        match tile {
+            TileType::Wall => tiles.wall_idx,
+            TileType::Floor if biome.decorated => tiles.decoration_idx,
+            TileType::Floor => tiles.floor_idx,
+            _ => registry.get(tile).texture_idx,
+        }
     }
 }
 
 fn setup(mut commands: Commands, tileset: Res<Tileset>, config: Res<MapConfig>) {
-    let texture_handle = tileset.0.clone();
-    let map_size = TilemapSize { x: 256, y: 256 };
+    spawn_tilemap(&mut commands, tileset.0.clone(), &config);
+}
+
+/// Respawns the tilemap when the map or tile size in [`MapConfig`] no longer matches it, once the
+/// tiles of a whole map that size are sent, so the old cave stays until the new one replaces it.
+fn resize_tilemap(
+    mut events: EventReader<SetTiles>,
+    mut commands: Commands,
+    tileset: Res<Tileset>,
+    config: Res<MapConfig>,
+    tilemaps: Query<(Entity, &TileStorage, &TilemapSize, &TilemapTileSize), Without<AnimatedLayer>>,
+) {
+    let whole_map = events.read().fold(false, |whole_map, SetTiles(tiles)| {
+        whole_map || tiles.len() == config.size.count()
+    });
+    if !whole_map {
+        return;
+    }
+    for (entity, storage, size, tile_size) in tilemaps.iter() {
+        if *size == config.size && *tile_size == config.tile_size {
+            continue;
+        }
+        debug!("resize tilemap to {:?}", config.size);
+        for tile in storage.iter().flatten() {
+            commands.entity(*tile).despawn();
+        }
+        commands.entity(entity).despawn_recursive();
+        spawn_tilemap(&mut commands, tileset.0.clone(), &config);
+    }
+}
+
+fn spawn_tilemap(commands: &mut Commands, texture_handle: Handle<Image>, config: &MapConfig) {
+    let map_size = config.size;
 
     // Create a tilemap entity a little early.
     // We want this entity early because we need to tell each tile which tilemap entity
@@ -79,14 +184,15 @@ fn setup(mut commands: Commands, tileset: Res<Tileset>, config: Res<MapConfig>)
                         ..Default::default()
                     },
                     TileType::Wall,
+                    TileBiome::default(),
                 ))
                 .id();
             tile_storage.set(&tile_pos, tile_entity);
         }
     }
 
-    let tile_size = TilemapTileSize { x: 12.0, y: 12.0 };
-    let grid_size = tile_size.into();
+    let tile_size = config.tile_size;
+    let grid_size = config.grid_size;
     let map_type = TilemapType::default();
 
     commands.entity(tilemap_entity).insert(TilemapBundle {
@@ -96,7 +202,7 @@ fn setup(mut commands: Commands, tileset: Res<Tileset>, config: Res<MapConfig>)
         storage: tile_storage,
         texture: TilemapTexture::Single(texture_handle),
         tile_size,
-        transform: get_tilemap_center_transform(&map_size, &grid_size, &map_type, 0.0),
+        transform: coords::tilemap_transform(config, 0.0),
         ..Default::default()
     });
 }
@@ -114,83 +220,385 @@ impl FromWorld for Tileset {
 #[derive(Event)]
 pub struct SetTiles(pub Vec<(TilePos, TileType)>);
 
-#[derive(Component)]
+/// Sets the biome of tiles, sent before the [`SetTiles`] of the same cave.
+#[derive(Event)]
+pub struct SetBiomes(pub Vec<(TilePos, TileBiome)>);
+
+#[derive(
+    Component, Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Reflect, Serialize, Deserialize,
+)]
 pub enum TileType {
+    #[default]
     Wall,
     Floor,
+    /// Pools on the floor of wet basins.
+    Water,
+    /// Veins through crystal walls.
+    Ore,
+    /// Crumbly wall.
+    SoftRock,
+    /// Droppings heaped on the floor under roosts.
+    Guano,
+    /// The unbreakable edge of the map.
+    Bedrock,
+}
+
+impl TileType {
+    pub const ALL: [TileType; 7] = [
+        TileType::Wall,
+        TileType::Floor,
+        TileType::Water,
+        TileType::Ore,
+        TileType::SoftRock,
+        TileType::Guano,
+        TileType::Bedrock,
+    ];
+}
+
+/// What physics sees of a tile.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TileCollider {
+    None,
+    Solid,
+    /// Detects what passes through without stopping it.
+    Sensor,
+}
+
+/// Everything that follows from a tile being of one [`TileType`].
+#[derive(Clone, Copy, Debug)]
+pub struct TileProperties {
+    /// Index into `tiles/TileSet.png`, see [`MapConfig::texture`].
+    pub texture_idx: u32,
+    /// Whether the tile counts as wall, blocking paths and shaping caves.
+    pub solid: bool,
+    pub collider: TileCollider,
+    pub friction: f32,
+    /// Fraction of a sound's energy lost bouncing off the tile.
+    pub absorption: f32,
+    /// Cost of moving through the tile when pathfinding, `None` if it can't be moved through.
+    pub path_cost: Option<u32>,
+    /// Whether the tile can be dug out or blown up.
+    pub breakable: bool,
+}
+
+/// Properties of every [`TileType`]. Register a new kind here after adding it to the enum.
+#[derive(Resource)]
+pub struct TileRegistry(HashMap<TileType, TileProperties>);
+
+impl TileRegistry {
+    pub fn register(&mut self, tile: TileType, properties: TileProperties) {
+        self.0.insert(tile, properties);
+    }
+
+    /// The properties of `tile`, or of [`TileType::Wall`] if it was never registered.
+    pub fn get(&self, tile: TileType) -> &TileProperties {
+        self.0
+            .get(&tile)
+            .or_else(|| self.0.get(&TileType::Wall))
+            .expect("walls are always registered")
+    }
+}
+
+impl Default for TileRegistry {
+    fn default() -> Self {
+        let wall = TileProperties {
+            texture_idx: WALL,
+            solid: true,
+            collider: TileCollider::Solid,
+            friction: 0.5,
+            absorption: 0.1,
+            path_cost: None,
+            breakable: true,
+        };
+        let floor = TileProperties {
+            texture_idx: FLOOR,
+            solid: false,
+            collider: TileCollider::None,
+            friction: 0.0,
+            absorption: 0.0,
+            path_cost: Some(1),
+            breakable: true,
+        };
+        let mut registry = Self(HashMap::default());
+        registry.register(TileType::Wall, wall);
+        registry.register(TileType::Floor, floor);
+        registry.register(
+            TileType::Water,
+            TileProperties {
+                texture_idx: 40,
+                collider: TileCollider::Sensor,
+                absorption: 0.02,
+                path_cost: Some(4),
+                ..floor
+            },
+        );
+        registry.register(
+            TileType::Ore,
+            TileProperties {
+                texture_idx: 244,
+                friction: 0.6,
+                absorption: 0.05,
+                ..wall
+            },
+        );
+        registry.register(
+            TileType::SoftRock,
+            TileProperties {
+                texture_idx: 71,
+                friction: 0.8,
+                absorption: 0.3,
+                ..wall
+            },
+        );
+        registry.register(
+            TileType::Guano,
+            TileProperties {
+                texture_idx: 246,
+                absorption: 0.7,
+                path_cost: Some(3),
+                ..floor
+            },
+        );
+        registry.register(
+            TileType::Bedrock,
+            TileProperties {
+                texture_idx: 60,
+                absorption: 0.0,
+                breakable: false,
+                ..wall
+            },
+        );
+        registry
+    }
+}
+
+/// What part of the cave a tile is in, changing how it looks and sounds.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Reflect, Serialize, Deserialize)]
+pub enum Biome {
+    #[default]
+    Dry,
+    Wet,
+    Crystal,
+    Guano,
+}
+
+impl Biome {
+    pub const ALL: [Biome; 4] = [Biome::Dry, Biome::Wet, Biome::Crystal, Biome::Guano];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Biome::Dry => "dry",
+            Biome::Wet => "wet",
+            Biome::Crystal => "crystal",
+            Biome::Guano => "guano",
+        }
+    }
+
+    /// Fraction of a sound's energy lost each time it bounces off a wall in this biome.
+    pub fn absorption(self) -> f32 {
+        match self {
+            Biome::Dry => 0.1,
+            Biome::Wet => 0.05,
+            Biome::Crystal => 0.02,
+            Biome::Guano => 0.6,
+        }
+    }
+}
+
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TileBiome {
+    pub biome: Biome,
+    /// Whether the tile shows its biome's decoration instead of plain floor.
+    pub decorated: bool,
+}
+
+/// Textures of one [`Biome`], as indices into `tiles/TileSet.png`.
+#[derive(Clone, Copy, Debug)]
+pub struct BiomeTiles {
+    pub floor_idx: u32,
+    pub wall_idx: u32,
+    pub decoration_idx: u32,
 }
 
 fn set_tile_textures(
     mut events: EventReader<SetTiles>,
-    tile_storage: Single<&TileStorage>,
-    mut tiles: Query<(Entity, &mut TileTextureIndex, &mut TileType)>,
+    mut biome_events: EventReader<SetBiomes>,
+    tile_storage: Single<&TileStorage, Without<AnimatedLayer>>,
+    mut tiles: Query<(&mut TileTextureIndex, &mut TileType, &mut TileBiome)>,
     config: Res<MapConfig>,
+    registry: Res<TileRegistry>,
 ) {
+    for SetBiomes(biomes) in biome_events.read() {
+        for (pos, biome) in biomes.iter() {
+            // tiles generated for a map size that has since changed
+            let Some(entity) = tile_storage.checked_get(pos) else {
+                continue;
+            };
+            let Ok((mut idx, tile_type, mut tile_biome)) = tiles.get_mut(entity) else {
+                continue;
+            };
+            *tile_biome = *biome;
+            idx.0 = config.texture(*tile_type, *biome, &registry);
+        }
+    }
     for event in events.read() {
-        let entities = event
-            .0
-            .iter()
-            .map(|(pos, _)| tile_storage.get(pos).unwrap());
-        let mut indices = tiles.iter_many_mut(entities);
-        let mut i = 0;
-        while let Some((entity, mut idx, mut tile_type)) = indices.fetch_next() {
-            let tile = &event.0[i].1;
-            i += 1;
-            match tile {
-                TileType::Floor => {
-                    idx.0 = config.floor_idx;
-                    *tile_type = TileType::Floor;
-                }
-                TileType::Wall => {
-                    idx.0 = config.wall_idx;
-                    *tile_type = TileType::Wall;
-                }
-            }
+        for (pos, tile) in event.0.iter() {
+            // tiles generated for a map size that has since changed
+            let Some(entity) = tile_storage.checked_get(pos) else {
+                continue;
+            };
+            let Ok((mut idx, mut tile_type, biome)) = tiles.get_mut(entity) else {
+                continue;
+            };
+            idx.0 = config.texture(*tile, *biome, &registry);
+            *tile_type = *tile;
         }
     }
 }
 
+/// Width and height in tiles of the square chunks the tilemap's colliders are split into, so an
+/// edit only rebuilds the chunks it touches.
+pub const COLLIDER_CHUNK_SIZE: u32 = 16;
+
+/// The collider of every tile of a [`TileType`] in a chunk, a child of the tilemap.
+#[derive(Component)]
+pub struct TileColliderChunk {
+    /// Position of the chunk in chunks, see [`COLLIDER_CHUNK_SIZE`].
+    pub chunk: UVec2,
+    pub tile: TileType,
+    /// Shapes, or polyline segments, the collider is made of.
+    pub shapes: usize,
+}
+
+/// Rebuilds the colliders of every chunk with a tile that has been set, or of all of them when
+/// [`MapConfig::collider_builder`] changes, one child per chunk and [`TileType`] so each kind can
+/// have its own friction or be a sensor.
+#[allow(clippy::too_many_arguments)]
 fn set_tilemap_collider(
     mut events: EventReader<SetTiles>,
-    tile_storage: Single<(Entity, &TileStorage)>,
+    tile_storage: Single<(Entity, &TileStorage, &Transform), Without<AnimatedLayer>>,
+    tiles: Query<&TileType>,
+    chunks: Query<(Entity, &TileColliderChunk)>,
     mut commands: Commands,
+    config: Res<MapConfig>,
+    registry: Res<TileRegistry>,
+    mut built_with: Local<Option<ColliderBuilder>>,
 ) {
-    for SetTiles(tiles) in events.read() {
-        let tile_collider = Collider::cuboid(6.0, 6.0);
-        let collider = Collider::compound(
-            tiles
-                .iter()
-                .filter_map(|(pos, tile)| {
-                    if let TileType::Floor = tile {
-                        return None;
-                    }
-                    let translation = Vect::new(pos.x as f32 * 12.0, pos.y as f32 * 12.0);
-                    Some((translation, Rot::default(), tile_collider.clone())) // cheap clone (internal Arc)
-                })
-                .collect_vec(),
+    let (tilemap, storage, transform) = *tile_storage;
+    let space = TileSpace::new(transform, &config);
+    let size = IVec2::new(storage.size.x as i32, storage.size.y as i32);
+    let chunk_of = |x: i32, y: i32| {
+        (IVec2::new(x, y).max(IVec2::ZERO) / COLLIDER_CHUNK_SIZE as i32).as_uvec2()
+    };
+    // a tile is a corner of the contour cells below and to the left of it too
+    let mut dirty: HashSet<UVec2> = events
+        .read()
+        .flat_map(|SetTiles(tiles)| tiles.iter())
+        .flat_map(|(pos, _)| {
+            let (x, y) = (pos.x as i32, pos.y as i32);
+            [(x, y), (x - 1, y), (x, y - 1), (x - 1, y - 1)].map(|(x, y)| chunk_of(x, y))
+        })
+        .collect();
+    if built_with.is_some_and(|builder| builder != config.collider_builder) {
+        dirty.extend(
+            (0..size.x)
+                .step_by(COLLIDER_CHUNK_SIZE as usize)
+                .cartesian_product((0..size.y).step_by(COLLIDER_CHUNK_SIZE as usize))
+                .map(|(x, y)| chunk_of(x, y)),
         );
-        commands
-            .entity(tile_storage.0)
-            .insert((collider, RigidBody::Fixed));
     }
-}
+    if dirty.is_empty() {
+        return;
+    }
+    *built_with = Some(config.collider_builder);
+    for (entity, chunk) in chunks.iter() {
+        if dirty.contains(&chunk.chunk) {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
 
-fn debug(
-    mut cursor: Local<Vec2>,
-    mut events: EventReader<CursorMoved>,
-    config: Res<MapConfig>,
-    camera: Single<(&Camera, &GlobalTransform)>,
-) {
-    if let Some(e) = events.read().last() {
-        *cursor = e.position;
+    let kind = |x: i32, y: i32| {
+        storage
+            .checked_get(&TilePos {
+                x: x as u32,
+                y: y as u32,
+            })
+            .and_then(|entity| tiles.get(entity).ok())
+            .copied()
+    };
+    let mut colliders = vec![];
+    for chunk in dirty {
+        let min = chunk.as_ivec2() * COLLIDER_CHUNK_SIZE as i32;
+        let max = (min + COLLIDER_CHUNK_SIZE as i32).min(size);
+        // contour cells along the top and right reach into the next chunks
+        let kinds: HashSet<TileType> = (min.x..=max.x)
+            .cartesian_product(min.y..=max.y)
+            .filter_map(|(x, y)| kind(x, y))
+            .filter(|tile| registry.get(*tile).collider != TileCollider::None)
+            .collect();
+        for tile in kinds {
+            // off the map counts as more of the tile at the edge, so no kind gets an edge along
+            // the border
+            let filled = |x: i32, y: i32| {
+                let edge = IVec2::new(x, y).clamp(IVec2::ZERO, size - 1);
+                kind(edge.x, edge.y) == Some(tile)
+            };
+            if let Some((collider, shapes)) =
+                config.collider_builder.build(min, max, filled, &space)
+            {
+                colliders.push((
+                    TileColliderChunk {
+                        chunk,
+                        tile,
+                        shapes,
+                    },
+                    collider,
+                ));
+            }
+        }
     }
 
-    let world = camera.0.viewport_to_world_2d(camera.1, *cursor).unwrap();
-    let tile = config.world_to_tile(world);
-    let back_to_world = tile.map(|pos| config.tile_to_world(pos));
+    commands
+        .entity(tilemap)
+        .insert(RigidBody::Fixed)
+        .with_children(|parent| {
+            for (chunk, collider) in colliders {
+                let properties = registry.get(chunk.tile);
+                let mut entity = parent.spawn((
+                    chunk,
+                    collider,
+                    Friction::coefficient(properties.friction),
+                    Transform::default(),
+                ));
+                if properties.collider == TileCollider::Sensor {
+                    entity.insert(Sensor);
+                }
+            }
+        });
+}
 
-    debug!(
-        "cursor: {:?}, world: {:?}, world_to_tile: {:?}, tile_to_world: {:?}",
-        cursor, world, tile, back_to_world
-    );
+/// Picks the [`ColliderBuilder`] and shows how many shapes the terrain's colliders are made of.
+fn ui(
+    mut contexts: EguiContexts,
+    mut config: ResMut<MapConfig>,
+    chunks: Query<&TileColliderChunk>,
+) {
+    egui::Window::new("Terrain").show(contexts.ctx_mut(), |ui| {
+        ui.horizontal(|ui| {
+            ui.label("colliders");
+            let mut builder = config.collider_builder;
+            for option in ColliderBuilder::ALL {
+                ui.radio_value(&mut builder, option, option.name());
+            }
+            // only touch the config on a change, resizing the tilemap runs whenever it's changed
+            if builder != config.collider_builder {
+                config.collider_builder = builder;
+            }
+        });
+        let shapes: usize = chunks.iter().map(|chunk| chunk.shapes).sum();
+        ui.label(format!(
+            "{shapes} collider shapes in {} chunks",
+            chunks.iter().count()
+        ));
+    });
 }
@@ -0,0 +1,132 @@
+use serde::{Deserialize, Serialize};
+
+use crate::prelude::*;
+
+use super::{coords, MapConfig};
+
+pub(super) fn animated_plugin(app: &mut App) {
+    app.add_event::<SetAnimated>();
+    app.init_resource::<AnimatedTileset>();
+    app.add_systems(Startup, setup);
+    app.add_systems(
+        Update,
+        (
+            resize_animated_layer.run_if(on_event::<SetAnimated>),
+            set_animated_tiles,
+        )
+            .chain(),
+    );
+}
+
+/// Frames in `tiles/EnvAnimated.png` are two tiles square, drawn up and to the right of their tile.
+pub const ANIMATED_TILE_SIZE: TilemapTileSize = TilemapTileSize { x: 24.0, y: 24.0 };
+
+/// Something animated drawn over the terrain, from `tiles/EnvAnimated.png`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Reflect, Serialize, Deserialize)]
+pub enum AnimatedKind {
+    /// Hangs on the side of a wall.
+    WallTorch,
+    /// Stands on the ground, two tiles tall.
+    StandingTorch,
+    /// Sits on the ground at the end of a tunnel.
+    Chest,
+}
+
+impl AnimatedKind {
+    pub const ALL: [AnimatedKind; 3] = [
+        AnimatedKind::WallTorch,
+        AnimatedKind::StandingTorch,
+        AnimatedKind::Chest,
+    ];
+}
+
+/// Replaces every animated tile with these, sent along with the [`SetTiles`](super::SetTiles) of a
+/// cave.
+#[derive(Event)]
+pub struct SetAnimated(pub Vec<(TilePos, AnimatedKind)>);
+
+/// The tilemap animated tiles are drawn on, over the terrain and the same size as it.
+#[derive(Component)]
+pub struct AnimatedLayer;
+
+#[derive(Resource)]
+pub struct AnimatedTileset(pub Handle<Image>);
+
+impl FromWorld for AnimatedTileset {
+    fn from_world(world: &mut World) -> Self {
+        Self(world.load_asset("tiles/EnvAnimated.png"))
+    }
+}
+
+fn setup(mut commands: Commands, tileset: Res<AnimatedTileset>, config: Res<MapConfig>) {
+    spawn_animated_layer(&mut commands, tileset.0.clone(), &config);
+}
+
+/// Respawns the layer empty when the map size in [`MapConfig`] no longer matches it, right before
+/// the new cave's animated tiles fill it again.
+fn resize_animated_layer(
+    mut commands: Commands,
+    tileset: Res<AnimatedTileset>,
+    config: Res<MapConfig>,
+    layers: Query<(Entity, &TileStorage, &TilemapSize), With<AnimatedLayer>>,
+) {
+    for (entity, storage, size) in layers.iter() {
+        if *size == config.size {
+            continue;
+        }
+        for tile in storage.iter().flatten() {
+            commands.entity(*tile).despawn();
+        }
+        commands.entity(entity).despawn_recursive();
+        spawn_animated_layer(&mut commands, tileset.0.clone(), &config);
+    }
+}
+
+fn spawn_animated_layer(commands: &mut Commands, texture: Handle<Image>, config: &MapConfig) {
+    commands.spawn((
+        AnimatedLayer,
+        TilemapBundle {
+            grid_size: config.grid_size,
+            size: config.size,
+            storage: TileStorage::empty(config.size),
+            texture: TilemapTexture::Single(texture),
+            tile_size: ANIMATED_TILE_SIZE,
+            transform: coords::tilemap_transform(config, 1.0),
+            ..Default::default()
+        },
+    ));
+}
+
+fn set_animated_tiles(
+    mut events: EventReader<SetAnimated>,
+    layer: Single<(Entity, &mut TileStorage), With<AnimatedLayer>>,
+    mut commands: Commands,
+    config: Res<MapConfig>,
+) {
+    let (layer, mut storage) = layer.into_inner();
+    for SetAnimated(tiles) in events.read() {
+        for tile in storage.iter().flatten() {
+            commands.entity(*tile).despawn();
+        }
+        *storage = TileStorage::empty(storage.size);
+        for (pos, kind) in tiles.iter() {
+            // tiles placed for a map size that has since changed
+            if pos.x >= storage.size.x || pos.y >= storage.size.y {
+                continue;
+            }
+            let animation = config.animations[*kind as usize];
+            let tile = commands
+                .spawn((
+                    TileBundle {
+                        position: *pos,
+                        tilemap_id: TilemapId(layer),
+                        texture_index: TileTextureIndex(animation.start),
+                        ..Default::default()
+                    },
+                    animation,
+                ))
+                .id();
+            storage.set(pos, tile);
+        }
+    }
+}
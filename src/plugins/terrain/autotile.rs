@@ -0,0 +1,253 @@
+use std::error::Error;
+
+use bevy::asset::{io::Reader, AssetLoader, LoadContext};
+use serde::Deserialize;
+
+use crate::prelude::*;
+
+use super::{
+    animated::AnimatedLayer, MapConfig, SetBiomes, SetTiles, TileBiome, TileRegistry, TileType,
+};
+
+/// The rule table for `tiles/TileSet.png`, kept next to it.
+const RULES_PATH: &str = "tiles/TileSet.autotile.ron";
+
+pub(super) fn autotile_plugin(app: &mut App) {
+    app.init_asset::<AutotileRules>();
+    app.register_asset_loader(AutotileLoader);
+    app.init_resource::<Autotiles>();
+}
+
+/// Which neighbours of a tile its texture depends on.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+pub enum Neighbourhood {
+    /// Only the four sharing an edge, rules about diagonals are ignored.
+    #[default]
+    Four,
+    Eight,
+}
+
+/// A neighbour of a tile, north being up the map.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub enum Direction {
+    N,
+    E,
+    S,
+    W,
+    NE,
+    SE,
+    SW,
+    NW,
+}
+
+impl Direction {
+    pub const ALL: [Direction; 8] = [
+        Direction::N,
+        Direction::E,
+        Direction::S,
+        Direction::W,
+        Direction::NE,
+        Direction::SE,
+        Direction::SW,
+        Direction::NW,
+    ];
+
+    pub fn offset(self) -> IVec2 {
+        match self {
+            Direction::N => IVec2::new(0, 1),
+            Direction::E => IVec2::new(1, 0),
+            Direction::S => IVec2::new(0, -1),
+            Direction::W => IVec2::new(-1, 0),
+            Direction::NE => IVec2::new(1, 1),
+            Direction::SE => IVec2::new(1, -1),
+            Direction::SW => IVec2::new(-1, -1),
+            Direction::NW => IVec2::new(-1, 1),
+        }
+    }
+
+    /// This neighbour's bit in a tile's neighbour mask, the edges in the low four bits.
+    pub fn bit(self) -> u8 {
+        1 << self as u8
+    }
+
+    fn mask(directions: &[Direction]) -> u8 {
+        directions
+            .iter()
+            .fold(0, |mask, direction| mask | direction.bit())
+    }
+}
+
+/// Swaps a tile's texture for a variant when its neighbours match.
+#[derive(Clone, Debug, Deserialize)]
+pub struct AutotileRule {
+    /// The texture the tile would have on its own, from [`MapConfig::texture`].
+    pub tile: u32,
+    /// Neighbours that must be as solid as the tile.
+    #[serde(default)]
+    pub same: Vec<Direction>,
+    /// Neighbours that must not be.
+    #[serde(default)]
+    pub different: Vec<Direction>,
+    /// The variant to use instead.
+    pub index: u32,
+}
+
+/// Texture variants picked by which neighbours of a tile are as solid as it is, loaded from
+/// [`RULES_PATH`].
+#[derive(Asset, TypePath, Clone, Debug, Default, Deserialize)]
+pub struct AutotileRules {
+    #[serde(default)]
+    pub neighbourhood: Neighbourhood,
+    /// Tried in order, the first that matches a tile wins. A tile no rule matches keeps its own
+    /// texture.
+    pub rules: Vec<AutotileRule>,
+}
+
+impl AutotileRules {
+    /// The texture for a tile with texture `tile`, where `mask` has the [`Direction::bit`] of each
+    /// neighbour that is as solid as it set.
+    pub fn resolve(&self, tile: u32, mask: u8) -> u32 {
+        let considered = match self.neighbourhood {
+            Neighbourhood::Four => 0b1111,
+            Neighbourhood::Eight => 0b1111_1111,
+        };
+        self.rules
+            .iter()
+            .filter(|rule| rule.tile == tile)
+            .find(|rule| {
+                let same = Direction::mask(&rule.same) & considered;
+                let different = Direction::mask(&rule.different) & considered;
+                mask & same == same && mask & different == 0
+            })
+            .map_or(tile, |rule| rule.index)
+    }
+}
+
+#[derive(Default)]
+struct AutotileLoader;
+
+impl AssetLoader for AutotileLoader {
+    type Asset = AutotileRules;
+    type Settings = ();
+    type Error = Box<dyn Error + Send + Sync>;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<AutotileRules, Self::Error> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes).await?;
+        Ok(ron::de::from_bytes(&bytes)?)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["autotile.ron"]
+    }
+}
+
+#[derive(Resource)]
+pub struct Autotiles(pub Handle<AutotileRules>);
+
+impl FromWorld for Autotiles {
+    fn from_world(world: &mut World) -> Self {
+        Self(world.load_asset(RULES_PATH))
+    }
+}
+
+/// Re-resolves the texture of every tile set since last frame and of the tiles around it, or of
+/// the whole map when the rules are loaded or edited. Runs after the textures are set, so it
+/// starts from each tile's own texture.
+#[allow(clippy::too_many_arguments)]
+pub(super) fn resolve_autotiles(
+    mut tile_events: EventReader<SetTiles>,
+    mut biome_events: EventReader<SetBiomes>,
+    mut rule_events: EventReader<AssetEvent<AutotileRules>>,
+    autotiles: Res<Autotiles>,
+    rules: Res<Assets<AutotileRules>>,
+    tilemap: Single<(&TileStorage, &TilemapSize), Without<AnimatedLayer>>,
+    mut tiles: Query<(&TileType, &TileBiome, &mut TileTextureIndex)>,
+    config: Res<MapConfig>,
+    registry: Res<TileRegistry>,
+) {
+    let (storage, size) = *tilemap;
+    let reloaded = rule_events.read().fold(false, |reloaded, event| {
+        reloaded
+            || event.is_loaded_with_dependencies(&autotiles.0)
+            || event.is_modified(&autotiles.0)
+    });
+    let Some(rules) = rules.get(&autotiles.0) else {
+        return;
+    };
+
+    let changed = tile_events
+        .read()
+        .flat_map(|SetTiles(tiles)| tiles.iter().map(|(pos, _)| *pos))
+        .chain(
+            biome_events
+                .read()
+                .flat_map(|SetBiomes(biomes)| biomes.iter().map(|(pos, _)| *pos)),
+        )
+        .collect_vec();
+    if changed.is_empty() && !reloaded {
+        return;
+    }
+
+    let index = |x: i32, y: i32| (y * size.x as i32 + x) as usize;
+    let in_map = |x: i32, y: i32| x >= 0 && y >= 0 && x < size.x as i32 && y < size.y as i32;
+    let mut dirty = vec![reloaded; size.count()];
+    for pos in changed {
+        for x in pos.x as i32 - 1..=pos.x as i32 + 1 {
+            for y in pos.y as i32 - 1..=pos.y as i32 + 1 {
+                if in_map(x, y) {
+                    dirty[index(x, y)] = true;
+                }
+            }
+        }
+    }
+
+    let solid = |x: i32, y: i32| {
+        storage
+            .checked_get(&TilePos {
+                x: x as u32,
+                y: y as u32,
+            })
+            .and_then(|entity| tiles.get(entity).ok())
+            .map(|(tile, ..)| registry.get(*tile).solid)
+    };
+    let mut textures = vec![];
+    for y in 0..size.y as i32 {
+        for x in 0..size.x as i32 {
+            if !dirty[index(x, y)] {
+                continue;
+            }
+            let Some(entity) = storage.checked_get(&TilePos {
+                x: x as u32,
+                y: y as u32,
+            }) else {
+                continue;
+            };
+            let Ok((tile, biome, _)) = tiles.get(entity) else {
+                continue;
+            };
+            let own = registry.get(*tile).solid;
+            // the edge of the map is treated as more of the same, so nothing is drawn along it
+            let mask = Direction::ALL
+                .into_iter()
+                .filter(|direction| {
+                    let neighbour = IVec2::new(x, y) + direction.offset();
+                    !in_map(neighbour.x, neighbour.y)
+                        || solid(neighbour.x, neighbour.y) == Some(own)
+                })
+                .fold(0, |mask, direction| mask | direction.bit());
+            let texture = rules.resolve(config.texture(*tile, *biome, &registry), mask);
+            textures.push((entity, texture));
+        }
+    }
+    for (entity, texture) in textures {
+        if let Ok((_, _, mut idx)) = tiles.get_mut(entity) {
+            idx.0 = texture;
+        }
+    }
+}
@@ -0,0 +1,238 @@
+use crate::prelude::*;
+
+use super::coords::TileSpace;
+
+/// How the tiles of a chunk are turned into collision shapes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColliderBuilder {
+    /// A cuboid per tile. Simple, but bodies snag on the seams between them.
+    Tiles,
+    /// As few cuboids as greedily merging runs of tiles into rectangles gives.
+    #[default]
+    Rectangles,
+    /// Only the outline of the tiles, traced with marching squares into a polyline.
+    Contours,
+}
+
+impl ColliderBuilder {
+    pub const ALL: [ColliderBuilder; 3] = [
+        ColliderBuilder::Tiles,
+        ColliderBuilder::Rectangles,
+        ColliderBuilder::Contours,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ColliderBuilder::Tiles => "tiles",
+            ColliderBuilder::Rectangles => "rectangles",
+            ColliderBuilder::Contours => "contours",
+        }
+    }
+
+    /// The collider of the `filled` tiles from `min` up to but not including `max`, and how many
+    /// shapes or polyline segments it's made of. `filled` is also asked about the tiles around the
+    /// area, including ones off the map. The collider is placed relative to the tilemap.
+    pub fn build(
+        self,
+        min: IVec2,
+        max: IVec2,
+        filled: impl Fn(i32, i32) -> bool,
+        space: &TileSpace,
+    ) -> Option<(Collider, usize)> {
+        match self {
+            ColliderBuilder::Tiles => {
+                let half = space.half_extents(Vec2::ONE);
+                let tile = Collider::cuboid(half.x, half.y);
+                let shapes = (min.x..max.x)
+                    .cartesian_product(min.y..max.y)
+                    .filter(|(x, y)| filled(*x, *y))
+                    .map(|(x, y)| {
+                        let translation = space.to_local(Vec2::new(x as f32, y as f32) + 0.5);
+                        (translation, Rot::default(), tile.clone()) // cheap clone (internal Arc)
+                    })
+                    .collect_vec();
+                compound(shapes)
+            }
+            ColliderBuilder::Rectangles => {
+                let shapes = rectangles(min, max, filled)
+                    .into_iter()
+                    .map(|rect| {
+                        let size = rect.size().as_vec2();
+                        let half = space.half_extents(size);
+                        let centre = space.to_local(rect.min.as_vec2() + size / 2.0);
+                        (centre, Rot::default(), Collider::cuboid(half.x, half.y))
+                    })
+                    .collect_vec();
+                compound(shapes)
+            }
+            ColliderBuilder::Contours => {
+                let segments = contours(min, max, filled);
+                if segments.is_empty() {
+                    return None;
+                }
+                let count = segments.len();
+                // segments run between tile centres, which are half a tile into each tile
+                let vertices = segments
+                    .iter()
+                    .flat_map(|[a, b]| [*a, *b].map(|point| space.to_local(point + 0.5)))
+                    .collect_vec();
+                let indices = (0..count as u32).map(|i| [i * 2, i * 2 + 1]).collect();
+                Some((Collider::polyline(vertices, Some(indices)), count))
+            }
+        }
+    }
+}
+
+fn compound(shapes: Vec<(Vect, Rot, Collider)>) -> Option<(Collider, usize)> {
+    let count = shapes.len();
+    (count > 0).then(|| (Collider::compound(shapes), count))
+}
+
+/// Covers the filled tiles with rectangles, each grown as wide as it can go along its row and then
+/// as tall as the tiles above allow.
+pub fn rectangles(min: IVec2, max: IVec2, filled: impl Fn(i32, i32) -> bool) -> Vec<IRect> {
+    let size = (max - min).max(IVec2::ZERO);
+    let index = |x: i32, y: i32| ((y - min.y) * size.x + (x - min.x)) as usize;
+    let mut covered = vec![false; (size.x * size.y) as usize];
+    let open = |covered: &[bool], x: i32, y: i32| filled(x, y) && !covered[index(x, y)];
+    let mut rects = vec![];
+    for y in min.y..max.y {
+        for x in min.x..max.x {
+            if !open(&covered, x, y) {
+                continue;
+            }
+            let mut right = x + 1;
+            while right < max.x && open(&covered, right, y) {
+                right += 1;
+            }
+            let mut top = y + 1;
+            while top < max.y && (x..right).all(|x| open(&covered, x, top)) {
+                top += 1;
+            }
+            for (x, y) in (x..right).cartesian_product(y..top) {
+                covered[index(x, y)] = true;
+            }
+            rects.push(IRect::new(x, y, right, top));
+        }
+    }
+    rects
+}
+
+/// The outline of the filled tiles as segments in tiles, by marching squares over the cells
+/// between tile centres. The cells run from those on the lower left corner of `min`, when it's at
+/// the edge of the map, up to those on the lower left of `max`, so areas next to each other
+/// don't trace the same cell twice.
+pub fn contours(min: IVec2, max: IVec2, filled: impl Fn(i32, i32) -> bool) -> Vec<[Vec2; 2]> {
+    let start = IVec2::new(
+        if min.x == 0 { -1 } else { min.x },
+        if min.y == 0 { -1 } else { min.y },
+    );
+    let mut segments = vec![];
+    for (x, y) in (start.x..max.x).cartesian_product(start.y..max.y) {
+        let case = filled(x, y) as u8
+            | (filled(x + 1, y) as u8) << 1
+            | (filled(x + 1, y + 1) as u8) << 2
+            | (filled(x, y + 1) as u8) << 3;
+        let origin = Vec2::new(x as f32, y as f32);
+        let bottom = origin + Vec2::new(0.5, 0.0);
+        let right = origin + Vec2::new(1.0, 0.5);
+        let top = origin + Vec2::new(0.5, 1.0);
+        let left = origin + Vec2::new(0.0, 0.5);
+        // saddles keep the two filled corners joined
+        match case {
+            1 | 14 => segments.push([left, bottom]),
+            2 | 13 => segments.push([bottom, right]),
+            3 | 12 => segments.push([left, right]),
+            4 | 11 => segments.push([right, top]),
+            5 => segments.extend([[bottom, right], [left, top]]),
+            6 | 9 => segments.push([bottom, top]),
+            7 | 8 => segments.push([left, top]),
+            10 => segments.extend([[left, bottom], [right, top]]),
+            _ => {}
+        }
+    }
+    segments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIN: IVec2 = IVec2::ZERO;
+    const MAX: IVec2 = IVec2::splat(16);
+
+    fn area(rects: &[IRect]) -> i32 {
+        rects.iter().map(|rect| rect.width() * rect.height()).sum()
+    }
+
+    /// Each end of every segment is shared with exactly one other segment.
+    fn closed(segments: &[[Vec2; 2]]) -> bool {
+        let ends = segments.iter().flatten().collect_vec();
+        ends.iter()
+            .all(|end| ends.iter().filter(|other| other == &end).count() == 2)
+    }
+
+    #[test]
+    fn full_chunk_is_one_rectangle() {
+        let rects = rectangles(MIN, MAX, |_, _| true);
+        assert_eq!(rects, vec![IRect::from_corners(MIN, MAX)]);
+    }
+
+    #[test]
+    fn l_shape_is_two_rectangles() {
+        let filled = |x: i32, y: i32| (x < 2 && y < 6) || (x < 5 && y < 2);
+        let rects = rectangles(MIN, MAX, filled);
+        assert_eq!(rects.len(), 2);
+        assert_eq!(area(&rects), 2 * 6 + 3 * 2);
+    }
+
+    #[test]
+    fn rectangles_cover_each_tile_once() {
+        let filled = |x: i32, y: i32| (x * 7 + y * 3 + x * y) % 5 < 3;
+        let rects = rectangles(MIN, MAX, filled);
+        for (x, y) in (MIN.x..MAX.x).cartesian_product(MIN.y..MAX.y) {
+            let covering = rects
+                .iter()
+                .filter(|rect| {
+                    x >= rect.min.x && x < rect.max.x && y >= rect.min.y && y < rect.max.y
+                })
+                .count();
+            assert_eq!(covering, filled(x, y) as usize, "tile ({x}, {y})");
+        }
+    }
+
+    #[test]
+    fn single_tile_contour_is_a_closed_loop() {
+        let segments = contours(MIN, MAX, |x, y| (x, y) == (5, 5));
+        assert_eq!(segments.len(), 4);
+        assert!(closed(&segments));
+    }
+
+    #[test]
+    fn diagonal_tiles_join_at_saddle() {
+        // the saddle in the cell between them joins them into one loop
+        let segments = contours(MIN, MAX, |x, y| (x, y) == (5, 5) || (x, y) == (6, 6));
+        assert_eq!(segments.len(), 8);
+        assert!(closed(&segments));
+    }
+
+    #[test]
+    fn chunks_trace_each_cell_once() {
+        let filled = |x: i32, y: i32| {
+            x < 0 || y < 0 || x >= 32 || y >= 32 || (x * 7 + y * 3 + x * y) % 5 < 3
+        };
+        let whole = contours(IVec2::ZERO, IVec2::splat(32), filled);
+        let chunks = (0..32)
+            .step_by(16)
+            .cartesian_product((0..32).step_by(16))
+            .flat_map(|(x, y)| {
+                let min = IVec2::new(x, y);
+                contours(min, min + 16, filled)
+            })
+            .collect_vec();
+        let key = |segment: &[Vec2; 2]| format!("{segment:?}");
+        let sorted = |segments: &[[Vec2; 2]]| segments.iter().map(key).sorted().collect_vec();
+        assert_eq!(sorted(&chunks), sorted(&whole));
+        assert!(closed(&whole));
+    }
+}
@@ -0,0 +1,223 @@
+use bevy::ecs::system::SystemParam;
+
+use crate::prelude::*;
+
+use super::{animated::AnimatedLayer, MapConfig};
+
+/// Where the terrain tilemap is spawned, centred on the world origin. The centre of tile `(0, 0)`
+/// is at the tilemap's translation.
+pub fn tilemap_transform(config: &MapConfig, z: f32) -> Transform {
+    let size = Vec2::new(config.size.x as f32, config.size.y as f32);
+    let grid = Vec2::new(config.grid_size.x, config.grid_size.y);
+    Transform::from_translation((-(size - 1.0) * grid / 2.0).extend(z))
+}
+
+/// Converts between positions in the world, in tiles and relative to the tilemap, so tiles,
+/// colliders, pathfinding and tools all agree on where a tile is.
+///
+/// Positions in tiles are continuous, `(0, 0)` being the bottom left corner of the map and tile
+/// `(x, y)` covering `x..x + 1` and `y..y + 1`, the same as cave graphs and masks.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TileSpace {
+    /// World position of the bottom left corner of the map.
+    pub corner: Vec2,
+    /// Distance between the centres of neighbouring tiles.
+    pub grid: Vec2,
+    /// Size of a tile's collider, which may be smaller than the grid.
+    pub tile: Vec2,
+    pub size: UVec2,
+}
+
+impl TileSpace {
+    /// The space of a tilemap with `transform`, which is only translated.
+    pub fn new(transform: &Transform, config: &MapConfig) -> Self {
+        let grid = Vec2::new(config.grid_size.x, config.grid_size.y);
+        Self {
+            corner: transform.translation.truncate() - grid / 2.0,
+            grid,
+            tile: Vec2::new(config.tile_size.x, config.tile_size.y),
+            size: UVec2::new(config.size.x, config.size.y),
+        }
+    }
+
+    /// The space of the tilemap as [`tilemap_transform`] places it.
+    pub fn from_config(config: &MapConfig) -> Self {
+        Self::new(&tilemap_transform(config, 0.0), config)
+    }
+
+    pub fn to_world(&self, tiles: Vec2) -> Vec2 {
+        self.corner + tiles * self.grid
+    }
+
+    pub fn to_tiles(&self, world: Vec2) -> Vec2 {
+        (world - self.corner) / self.grid
+    }
+
+    /// Relative to the tilemap entity, for its children such as colliders.
+    pub fn to_local(&self, tiles: Vec2) -> Vec2 {
+        (tiles - 0.5) * self.grid
+    }
+
+    pub fn tile_center(&self, pos: TilePos) -> Vec2 {
+        self.to_world(Vec2::new(pos.x as f32, pos.y as f32) + 0.5)
+    }
+
+    /// The tile under `world`, if it's on the map.
+    pub fn world_to_tile(&self, world: Vec2) -> Option<TilePos> {
+        let tiles = self.to_tiles(world).floor();
+        let on_map = tiles.cmpge(Vec2::ZERO).all() && tiles.cmplt(self.size.as_vec2()).all();
+        on_map.then(|| TilePos::new(tiles.x as u32, tiles.y as u32))
+    }
+
+    /// Half the size of a block of `tiles` tiles, from the outer edges of its outermost tiles'
+    /// colliders.
+    pub fn half_extents(&self, tiles: Vec2) -> Vec2 {
+        ((tiles - 1.0) * self.grid + self.tile) / 2.0
+    }
+}
+
+/// The [`TileSpace`] of the terrain tilemap, or of where it will be spawned while it's missing.
+#[derive(SystemParam)]
+#[allow(clippy::type_complexity)]
+pub struct TerrainSpace<'w> {
+    tilemap: Option<Single<'w, &'static Transform, (With<TileStorage>, Without<AnimatedLayer>)>>,
+    config: Res<'w, MapConfig>,
+}
+
+impl TerrainSpace<'_> {
+    pub fn get(&self) -> TileSpace {
+        match &self.tilemap {
+            Some(transform) => TileSpace::new(transform, &self.config),
+            None => TileSpace::from_config(&self.config),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(size: UVec2, grid: Vec2, tile: Vec2) -> MapConfig {
+        MapConfig {
+            size: TilemapSize {
+                x: size.x,
+                y: size.y,
+            },
+            grid_size: TilemapGridSize {
+                x: grid.x,
+                y: grid.y,
+            },
+            tile_size: TilemapTileSize {
+                x: tile.x,
+                y: tile.y,
+            },
+            ..default()
+        }
+    }
+
+    fn configs() -> Vec<MapConfig> {
+        vec![
+            config(UVec2::new(256, 256), Vec2::splat(12.0), Vec2::splat(12.0)),
+            config(UVec2::new(7, 5), Vec2::splat(12.0), Vec2::splat(12.0)),
+            config(UVec2::new(9, 4), Vec2::new(12.0, 8.0), Vec2::new(12.0, 8.0)),
+            config(UVec2::new(1, 3), Vec2::new(5.0, 16.0), Vec2::new(4.0, 14.0)),
+        ]
+    }
+
+    fn tiles(space: &TileSpace) -> Vec<TilePos> {
+        (0..space.size.x)
+            .cartesian_product(0..space.size.y)
+            .map(|(x, y)| TilePos::new(x, y))
+            .collect()
+    }
+
+    #[test]
+    fn tile_centres_round_trip() {
+        for config in configs() {
+            let space = TileSpace::from_config(&config);
+            for pos in tiles(&space) {
+                assert_eq!(space.world_to_tile(space.tile_center(pos)), Some(pos));
+            }
+        }
+    }
+
+    #[test]
+    fn tile_corners_round_trip() {
+        for config in configs() {
+            let space = TileSpace::from_config(&config);
+            let inset = space.grid * 0.01;
+            for pos in tiles(&space) {
+                let min = space.to_world(Vec2::new(pos.x as f32, pos.y as f32));
+                let max = min + space.grid;
+                for corner in [
+                    min + inset,
+                    Vec2::new(max.x - inset.x, min.y + inset.y),
+                    Vec2::new(min.x + inset.x, max.y - inset.y),
+                    max - inset,
+                ] {
+                    assert_eq!(space.world_to_tile(corner), Some(pos));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn positions_round_trip() {
+        for config in configs() {
+            let space = TileSpace::from_config(&config);
+            for tiles in [Vec2::ZERO, Vec2::new(0.3, 2.7), space.size.as_vec2()] {
+                let back = space.to_tiles(space.to_world(tiles));
+                assert!(back.abs_diff_eq(tiles, 1e-4), "{tiles} came back as {back}");
+            }
+        }
+    }
+
+    #[test]
+    fn off_the_map_is_none() {
+        for config in configs() {
+            let space = TileSpace::from_config(&config);
+            let size = space.size.as_vec2();
+            for tiles in [
+                Vec2::new(-0.01, 0.5),
+                Vec2::new(0.5, -0.01),
+                Vec2::new(size.x + 0.01, 0.5),
+                Vec2::new(0.5, size.y),
+            ] {
+                assert_eq!(space.world_to_tile(space.to_world(tiles)), None);
+            }
+        }
+    }
+
+    #[test]
+    fn map_is_centred() {
+        for config in configs() {
+            let space = TileSpace::from_config(&config);
+            let centre = space.to_world(space.size.as_vec2() / 2.0);
+            assert!(centre.abs_diff_eq(Vec2::ZERO, 1e-3), "centre at {centre}");
+        }
+    }
+
+    #[test]
+    fn local_positions_match_tiles() {
+        for config in configs() {
+            let transform = tilemap_transform(&config, 0.0);
+            let space = TileSpace::new(&transform, &config);
+            for pos in tiles(&space) {
+                let tiles = Vec2::new(pos.x as f32, pos.y as f32) + 0.5;
+                let world = transform.translation.truncate() + space.to_local(tiles);
+                assert!(world.abs_diff_eq(space.tile_center(pos), 1e-3));
+            }
+        }
+    }
+
+    #[test]
+    fn half_extents_cover_tiles() {
+        for config in configs() {
+            let space = TileSpace::from_config(&config);
+            assert_eq!(space.half_extents(Vec2::ONE), space.tile / 2.0);
+            let block = Vec2::new(3.0, 2.0);
+            let span = space.to_local(block - 0.5) - space.to_local(Vec2::splat(0.5));
+            assert_eq!(space.half_extents(block) * 2.0, span + space.tile);
+        }
+    }
+}
@@ -6,3 +6,6 @@ pub use bevy_rapier2d::prelude::*;
 pub use itertools::*;
 pub use leafwing_input_manager::prelude::*;
 pub use rand::prelude::*;
+
+// names more than one of the globs above export, picked explicitly
+pub use bevy::prelude::{Real, Update};